@@ -0,0 +1,39 @@
+use calloop::EventLoop;
+use std::time::Duration;
+use wayland_input::{DummyConnector, IMEvent, InputService, InputSource};
+
+/// Shared data of the loop
+#[derive(Default)]
+struct State {
+    active: bool,
+}
+
+fn main() {
+    let service = InputService::new::<DummyConnector>(None);
+    let source = InputSource::new(service).expect("Unable to create the event source");
+    let service = source.service();
+
+    let mut event_loop: EventLoop<State> = EventLoop::try_new().unwrap();
+    let handle = event_loop.handle();
+    handle
+        .insert_source(source, move |event, _, state| {
+            println!("Received {event:?}");
+            match event {
+                IMEvent::Activate => state.active = true,
+                IMEvent::Deactivate => state.active = false,
+                IMEvent::Done if state.active => {
+                    let _ = service.commit_string("Hello".to_string());
+                    let _ = service.commit();
+                }
+                _ => (),
+            }
+        })
+        .unwrap();
+
+    let mut state = State::default();
+    loop {
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut state)
+            .unwrap();
+    }
+}
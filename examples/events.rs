@@ -0,0 +1,29 @@
+use wayland_input::{IMEvent, InputService};
+
+fn main() {
+    let service = InputService::builder()
+        .build()
+        .expect("Unable to connect to the wayland server");
+    let events = service.events();
+
+    let mut active = false;
+    loop {
+        if let Err(err) = service.dispatch() {
+            println!("The connection was lost: {err}");
+            return;
+        }
+        for event in events.try_iter() {
+            println!("Received {event:?}");
+            match event {
+                IMEvent::Activate => active = true,
+                IMEvent::Deactivate => active = false,
+                IMEvent::Done if active => {
+                    let _ = service.commit_string("Hello".to_string());
+                    let _ = service.commit();
+                }
+                IMEvent::Unavailable => return,
+                _ => (),
+            }
+        }
+    }
+}
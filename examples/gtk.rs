@@ -1,6 +1,30 @@
 use gtk::prelude::*;
 use gtk::{gio, glib};
-use wayland_input::DummyConnector;
+use wayland_input::{GlibInputService, IMConnector};
+use wayland_protocols::unstable::text_input::v3::client::zwp_text_input_v3::{
+    ChangeCause, ContentHint, ContentPurpose,
+};
+
+/// Prints the events of the input method. They arrive on the GTK main thread
+struct PrintConnector;
+
+impl IMConnector for PrintConnector {
+    fn activated(&self) {
+        println!("Input method activated");
+    }
+    fn deactivated(&self) {
+        println!("Input method deactivated");
+    }
+    fn surrounding_text(&self, text: String, cursor: usize, anchor: usize) {
+        println!("Surrounding text: {text:?}, cursor: {cursor}, anchor: {anchor}");
+    }
+    fn text_change_cause(&self, _: ChangeCause) {}
+    fn content_type(&self, _: ContentHint, _: ContentPurpose) {}
+    fn done(&self) {}
+    fn unavailable(&self) {
+        println!("Input method unavailable");
+    }
+}
 
 fn main() {
     let application = gtk::Application::new(
@@ -62,9 +86,16 @@ fn build_ui(application: &gtk::Application) {
 
     let button = gtk::Button::with_label("Input text!");
 
-    let mut vk_service = wayland_input::InputService::new::<DummyConnector>(None);
+    let service = wayland_input::InputService::new(Some(PrintConnector));
+    // Dispatch the events on the GTK main loop
+    let glib_service = GlibInputService::attach(service, &glib::MainContext::default())
+        .expect("Unable to attach the InputService to the main context");
+    let vk_service = glib_service.service();
+
+    let callback = move |_: &gtk::Button| {
+        // Keep the source attached as long as the button exists
+        let _ = &glib_service;
 
-    let mut callback = move |_| {
         // Enter a string
         let submission_result = vk_service.commit_string("Start typing".to_string());
         let submission_result = vk_service.commit();
@@ -96,7 +127,8 @@ fn build_ui(application: &gtk::Application) {
         };
         println!("Second toggle shift and long press x");
 
-        vk_service.sync_eventqueue();
+        // Send the requests right away instead of waiting for the next event
+        let _ = vk_service.flush();
     };
 
     button.connect_clicked(callback);
@@ -0,0 +1,27 @@
+use wayland_input::{AsyncInputService, IMEvent, InputService};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let service = InputService::try_new::<wayland_input::DummyConnector>(None)
+        .expect("Unable to connect to the wayland server");
+    let service = AsyncInputService::new(service).expect("Unable to register the connection");
+    let mut events = service.events();
+
+    let handle_events = async {
+        while let Some(event) = events.recv().await {
+            println!("Received {event:?}");
+            if event == IMEvent::Done {
+                if service.commit_string("Hello".to_string()).await.is_err() {
+                    println!("Error commit_string");
+                }
+                if service.commit().await.is_err() {
+                    println!("Error commit");
+                }
+            }
+        }
+    };
+    tokio::select! {
+        result = service.run() => println!("The connection was lost: {result:?}"),
+        _ = handle_events => (),
+    }
+}
@@ -0,0 +1,212 @@
+use crate::connection;
+use crate::{CommitStatus, IMEvent, InputService, KeyCode, KeyState, SubmitError};
+use futures_core::Stream;
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Drives an InputService with the tokio reactor instead of blocking on `sync_eventqueue`
+///
+/// The InputService is not Send, so the futures have to run on a current thread runtime or in a `tokio::task::LocalSet`.
+/// Waiting for the attempts of a ReconnectPolicy needs the time driver of the runtime.
+/// The events only get dispatched while the future returned by `run` or `dispatch` is polled
+#[derive(Debug)]
+pub struct AsyncInputService {
+    service: InputService,
+    /// Registration of the connection fd. It gets replaced when the InputService reconnects
+    fd: RefCell<Rc<AsyncFd<File>>>,
+    /// The connection the registered fd belongs to
+    connection_count: Cell<u32>,
+}
+
+impl AsyncInputService {
+    /// Registers the connection of the InputService with the tokio reactor
+    ///
+    /// Returns an error if it is not called from within a tokio runtime or if the InputService was created on an existing connection
+    pub fn new(service: InputService) -> io::Result<Self> {
+        service.require_own_event_queue()?;
+        let fd = register(service.connection_fd())?;
+        Ok(Self {
+            connection_count: Cell::new(service.connection_count()),
+            service,
+            fd: RefCell::new(Rc::new(fd)),
+        })
+    }
+
+    /// Returns the wrapped InputService
+    pub fn service(&self) -> &InputService {
+        &self.service
+    }
+
+    /// Returns a stream of the events the compositor sends to the input method
+    ///
+    /// The IMConnector still gets notified about the events
+    pub fn events(&self) -> EventStream {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.service
+            .add_event_sink(Box::new(move |event| sender.send(event.clone()).is_ok()));
+        EventStream { receiver }
+    }
+
+    /// Dispatches the events until the connection to the wayland server is lost
+    pub async fn run(&self) -> io::Result<()> {
+        loop {
+            self.dispatch().await?;
+        }
+    }
+
+    /// Waits until events were received and dispatches them. Returns the number of dispatched events
+    pub async fn dispatch(&self) -> io::Result<u32> {
+        loop {
+            let dispatched = self.service.dispatch_pending()?;
+            if let Some(deadline) = self.service.reconnect_deadline() {
+                // The lost connection is not polled until the next attempt
+                tokio::time::sleep_until(deadline.into()).await;
+                continue;
+            }
+            self.update_fd()?;
+            self.flush().await?;
+            if dispatched > 0 {
+                return Ok(dispatched);
+            }
+            // There are no events left in the queue, so wait for new ones
+            if let Some(read_guard) = self.service.prepare_read() {
+                let fd = self.fd.borrow().clone();
+                let mut ready_guard = fd.readable().await?;
+                match read_guard.read_events() {
+                    Ok(()) => (),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        ready_guard.clear_ready()
+                    }
+                    Err(err) => {
+                        self.service.connection_lost(err)?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the pending requests to the wayland server and waits if the socket is full
+    pub async fn flush(&self) -> io::Result<()> {
+        loop {
+            match self.service.flush() {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    let fd = self.fd.borrow().clone();
+                    fd.writable().await?.clear_ready();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends a 'commit_string' request to the wayland server
+    pub async fn commit_string(&self, text: String) -> Result<(), SubmitError> {
+        self.service.commit_string(text)?;
+        self.flush_submitted().await
+    }
+
+    /// Sends a 'delete_surrounding_text' request to the wayland server
+    pub async fn delete_surrounding_text(
+        &self,
+        before: usize,
+        after: usize,
+    ) -> Result<(), SubmitError> {
+        self.service.delete_surrounding_text(before, after)?;
+        self.flush_submitted().await
+    }
+
+    /// Sends a 'set_preedit_string' request to the wayland server
+    pub async fn set_preedit(
+        &self,
+        text: String,
+        cursor: Option<(usize, usize)>,
+    ) -> Result<(), SubmitError> {
+        self.service.set_preedit(text, cursor)?;
+        self.flush_submitted().await
+    }
+
+    /// Sends a 'commit' request to the wayland server
+    pub async fn commit(&self) -> Result<CommitStatus, SubmitError> {
+        let commit_status = self.service.commit()?;
+        self.flush_submitted().await?;
+        Ok(commit_status)
+    }
+
+    /// Destroys the input method
+    pub async fn make_unavailable(&self) -> Result<(), SubmitError> {
+        self.service.make_unavailable()?;
+        self.flush_submitted().await
+    }
+
+    /// Sends a key event with the virtual keyboard
+    pub async fn send_key(
+        &self,
+        keycode: KeyCode,
+        desired_key_state: KeyState,
+    ) -> Result<(), SubmitError> {
+        self.service.send_key(keycode, desired_key_state)?;
+        self.flush_submitted().await
+    }
+
+    /// Sets the modifiers of the virtual keyboard
+    pub async fn modifiers(
+        &self,
+        mods_depressed: u32,
+        mods_latched: u32,
+        mods_locked: u32,
+        group: u32,
+    ) -> Result<(), SubmitError> {
+        self.service
+            .modifiers(mods_depressed, mods_latched, mods_locked, group)?;
+        self.flush_submitted().await
+    }
+
+    /// Flushes a submitted request. The request is lost if the connection is broken
+    async fn flush_submitted(&self) -> Result<(), SubmitError> {
+        self.flush().await.map_err(|_| SubmitError::NotAlive)
+    }
+
+    /// Registers the new connection fd if the InputService reconnected
+    fn update_fd(&self) -> io::Result<()> {
+        let connection_count = self.service.connection_count();
+        if self.connection_count.get() != connection_count {
+            *self.fd.borrow_mut() = Rc::new(register(self.service.connection_fd())?);
+            self.connection_count.set(connection_count);
+        }
+        Ok(())
+    }
+}
+
+/// Registers a duplicate of the connection fd with the tokio reactor
+fn register(connection_fd: RawFd) -> io::Result<AsyncFd<File>> {
+    let file = connection::duplicate_fd(connection_fd)?;
+    // The file owns the fd, so it stays open as long as the AsyncFd exists
+    Ok(unsafe { AsyncFd::register(file) }?)
+}
+
+/// Stream of the events the compositor sends to the input method
+#[derive(Debug)]
+pub struct EventStream {
+    receiver: UnboundedReceiver<IMEvent>,
+}
+
+impl EventStream {
+    /// Waits for the next event. Returns None if the AsyncInputService was dropped
+    pub async fn recv(&mut self) -> Option<IMEvent> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = IMEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
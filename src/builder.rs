@@ -0,0 +1,263 @@
+use crate::connection::{self, ConnectionTarget};
+use crate::globals::{GlobalListener, Globals};
+use crate::handler::Handler;
+use crate::{
+    keymap, wayland, ConnectError, DummyConnector, IMConnector, IMHandler, InputService,
+    ReconnectPolicy, SeatSelector,
+};
+use std::ffi::OsString;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use wayland_client::{protocol::wl_seat::WlSeat, Display, GlobalManager, QueueToken};
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2;
+use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+
+/// Determines if a protocol gets used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Requirement {
+    /// Building the InputService fails if the protocol is unavailable
+    Required,
+    /// The protocol gets used if it is available
+    #[default]
+    Optional,
+    /// The protocol does not get used
+    Disabled,
+}
+
+/// Builder to configure which protocols the InputService uses and how it connects to the wayland server
+pub struct InputServiceBuilder {
+    input_method: Requirement,
+    virtual_keyboard: Requirement,
+    keymap: String,
+    seat_selector: SeatSelector,
+    connector: Rc<dyn IMConnector>,
+    connection_target: ConnectionTarget,
+    log_unhandled_events: bool,
+    chunk_commit_strings: bool,
+    handler: Option<Handler>,
+    reconnect_policy: Option<ReconnectPolicy>,
+}
+
+impl std::fmt::Debug for InputServiceBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InputServiceBuilder")
+            .field("input_method", &self.input_method)
+            .field("virtual_keyboard", &self.virtual_keyboard)
+            .field("seat_selector", &self.seat_selector)
+            .field("connection_target", &self.connection_target)
+            .field("log_unhandled_events", &self.log_unhandled_events)
+            .field("chunk_commit_strings", &self.chunk_commit_strings)
+            .field("reconnect_policy", &self.reconnect_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for InputServiceBuilder {
+    fn default() -> Self {
+        Self {
+            input_method: Requirement::Optional,
+            virtual_keyboard: Requirement::Optional,
+            keymap: keymap::KEYMAP.to_string(),
+            seat_selector: SeatSelector::First,
+            connector: Rc::new(DummyConnector::default()),
+            connection_target: ConnectionTarget::Env,
+            log_unhandled_events: true,
+            chunk_commit_strings: true,
+            handler: None,
+            reconnect_policy: None,
+        }
+    }
+}
+
+impl InputServiceBuilder {
+    /// Creates a builder that uses both protocols if they are available
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets if the zwp_input_method_v2 protocol gets used
+    pub fn input_method(mut self, requirement: Requirement) -> Self {
+        self.input_method = requirement;
+        self
+    }
+
+    /// Sets if the zwp_virtual_keyboard_v1 protocol gets used
+    pub fn virtual_keyboard(mut self, requirement: Requirement) -> Self {
+        self.virtual_keyboard = requirement;
+        self
+    }
+
+    /// Sets the XKB keymap (format xkb_v1) the virtual keyboard uses instead of the default one
+    pub fn keymap<S: Into<String>>(mut self, keymap: S) -> Self {
+        self.keymap = keymap.into();
+        self
+    }
+
+    /// Sets the selector for the wl_seat the protocols are used with
+    pub fn seat(mut self, seat_selector: SeatSelector) -> Self {
+        self.seat_selector = seat_selector;
+        self
+    }
+
+    /// Sets the connector that gets notified about the events of the input method
+    pub fn connector<C: IMConnector + 'static>(mut self, connector: C) -> Self {
+        self.connector = Rc::new(connector);
+        self
+    }
+
+    /// Sets how the wayland server gets connected to. By default the environment variables determine the socket
+    pub fn connection(mut self, connection_target: ConnectionTarget) -> Self {
+        self.connection_target = connection_target;
+        self
+    }
+
+    /// Connects to the socket with this name in `XDG_RUNTIME_DIR` instead of the one named by `WAYLAND_DISPLAY`
+    pub fn socket_name<S: Into<OsString>>(self, name: S) -> Self {
+        self.connection(ConnectionTarget::Name(name.into()))
+    }
+
+    /// Connects to the socket at this path instead of the one named by `WAYLAND_DISPLAY`
+    pub fn socket_path<P: Into<PathBuf>>(self, path: P) -> Self {
+        self.connection(ConnectionTarget::Path(path.into()))
+    }
+
+    /// Uses a socket that is already connected to the wayland server
+    pub fn socket(self, stream: UnixStream) -> Self {
+        self.connection(ConnectionTarget::Stream(stream))
+    }
+
+    /// Sets if events that are not handled by the InputService get printed. It is enabled by default
+    pub fn log_unhandled_events(mut self, enabled: bool) -> Self {
+        self.log_unhandled_events = enabled;
+        self
+    }
+
+    /// Sets if texts that are too long for a single 'commit_string' get split into several commits. It is enabled by default
+    ///
+    /// If it is disabled, `commit_string` returns SubmitError::TooLong for those texts
+    pub fn chunk_commit_strings(mut self, enabled: bool) -> Self {
+        self.chunk_commit_strings = enabled;
+        self
+    }
+
+    /// Sets the IMHandler that gets passed every event and can respond to it
+    ///
+    /// The InputService owns the handler. It can be accessed with `InputService::with_handler`
+    pub fn handler<H: IMHandler>(mut self, handler: H) -> Self {
+        self.handler = Some(Handler::new(handler));
+        self
+    }
+
+    /// Enables reconnecting when the connection to the wayland server gets lost
+    ///
+    /// The attempts are made by `InputService::dispatch_pending` once `InputService::reconnect_deadline` is reached
+    pub fn reconnect(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = Some(reconnect_policy);
+        self
+    }
+
+    /// Connects to the wayland server and creates the InputService
+    ///
+    /// Returns an error if no connection could be established, no wl_seat matches the selector or a required protocol is unavailable
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn build(mut self) -> Result<InputService, ConnectError> {
+        let (input_method, virtual_keyboard) = (self.input_method, self.virtual_keyboard);
+        let log_unhandled_events = self.log_unhandled_events;
+        let reconnect_policy = self.reconnect_policy;
+        let mut connection_target = Some(std::mem::take(&mut self.connection_target));
+        let display = connection::connect(&mut connection_target)?;
+        let globals = Arc::new(Mutex::new(self.globals()));
+        let global_listener = GlobalListener::default();
+        let (event_queue, _global_mgr) =
+            wayland::init_wayland(display, &globals, &global_listener)?;
+        check_requirements(&globals.lock().unwrap(), input_method, virtual_keyboard)?;
+        Ok(InputService {
+            display: Mutex::new(event_queue.display().clone()),
+            event_queue: Some(Arc::new(Mutex::new(event_queue))),
+            globals,
+            global_listener,
+            connection_target: Mutex::new(connection_target),
+            connection_count: Mutex::new(1),
+            stopper: Mutex::new(None),
+            log_unhandled_events,
+            reconnect_policy,
+            pending_reconnect: Mutex::new(None),
+        })
+    }
+
+    /// Creates the InputService on top of an existing wayland connection
+    ///
+    /// The input method and the virtual keyboard are created on the event queue of the `token`.
+    /// The application has to dispatch that event queue itself, otherwise the `IMConnector` never gets notified.
+    /// The `global_mgr` needs to have received the list of globals already.
+    /// Globals that appear or disappear later on are not tracked, the seat selector is ignored and reconnecting is not possible.
+    ///
+    /// Returns an error if a required protocol is unavailable
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn build_from_connection(
+        self,
+        display: &Display,
+        token: QueueToken,
+        global_mgr: &GlobalManager,
+        seat: &WlSeat,
+    ) -> Result<InputService, ConnectError> {
+        let (input_method, virtual_keyboard) = (self.input_method, self.virtual_keyboard);
+        let log_unhandled_events = self.log_unhandled_events;
+        let (im_mgr, vk_mgr) = wayland::get_managers(global_mgr);
+        // Attach the managers to the event queue of the application so the objects they create are handled by it
+        let im_mgr = im_mgr.ok().map(|im_mgr| {
+            (
+                wayland::global_id::<ZwpInputMethodManagerV2>(global_mgr).unwrap_or_default(),
+                im_mgr.as_ref().attach(token.clone()),
+            )
+        });
+        let vk_mgr = vk_mgr.ok().map(|vk_mgr| {
+            (
+                wayland::global_id::<ZwpVirtualKeyboardManagerV1>(global_mgr).unwrap_or_default(),
+                vk_mgr.as_ref().attach(token),
+            )
+        });
+        let mut globals = self.globals();
+        globals.set_external(seat, im_mgr, vk_mgr);
+        check_requirements(&globals, input_method, virtual_keyboard)?;
+        Ok(InputService {
+            display: Mutex::new(display.clone()),
+            event_queue: None,
+            globals: Arc::new(Mutex::new(globals)),
+            global_listener: GlobalListener::default(),
+            connection_target: Mutex::new(None),
+            connection_count: Mutex::new(1),
+            stopper: Mutex::new(None),
+            log_unhandled_events,
+            reconnect_policy: None,
+            pending_reconnect: Mutex::new(None),
+        })
+    }
+
+    /// Creates the Globals that bind the enabled protocols
+    fn globals(self) -> Globals {
+        let mut globals = Globals::new(self.seat_selector, self.connector, self.keymap);
+        globals.handler = self.handler;
+        globals.chunk_commit_strings = self.chunk_commit_strings;
+        globals.input_method_enabled = self.input_method != Requirement::Disabled;
+        globals.virtual_keyboard_enabled = self.virtual_keyboard != Requirement::Disabled;
+        globals
+    }
+}
+
+/// Returns an error if a required protocol is unavailable
+fn check_requirements(
+    globals: &Globals,
+    input_method: Requirement,
+    virtual_keyboard: Requirement,
+) -> Result<(), ConnectError> {
+    if input_method == Requirement::Required && globals.im.is_none() {
+        return Err(ConnectError::InputMethodUnavailable);
+    }
+    if virtual_keyboard == Requirement::Required && globals.vk.is_none() {
+        return Err(ConnectError::VirtualKeyboardUnavailable);
+    }
+    Ok(())
+}
@@ -0,0 +1,299 @@
+use crate::connection;
+use crate::{IMEvent, InputService, KeyCode, KeyState};
+use calloop::generic::Generic;
+use calloop::timer::{TimeoutAction, Timer};
+use calloop::{
+    EventSource, Interest, LoopHandle, Mode, Poll, PostAction, Readiness, RegistrationToken, Token,
+    TokenFactory,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Calloop event source that dispatches the events of an InputService
+///
+/// The events of the input method are passed to the callback of the source, which has access to the shared data of the loop.
+/// The pending requests get flushed before the loop goes to sleep
+#[derive(Debug)]
+pub struct InputSource {
+    service: Rc<InputService>,
+    /// Registration of a duplicate of the connection fd. It gets replaced when the InputService reconnects
+    source: Generic<File>,
+    /// Timer of the next attempt to reconnect. It is registered instead of the lost connection fd
+    timer: Option<Timer>,
+    /// The connection the registered fd belongs to
+    connection_count: u32,
+    /// Events that were dispatched but not yet passed to the callback
+    events: Rc<RefCell<VecDeque<IMEvent>>>,
+}
+
+impl InputSource {
+    /// Creates an event source for the InputService
+    ///
+    /// Returns an error if the InputService was created on an existing connection
+    pub fn new(service: InputService) -> io::Result<Self> {
+        service.require_own_event_queue()?;
+        let events = Rc::new(RefCell::new(VecDeque::new()));
+        let sink_events = Rc::downgrade(&events);
+        service.add_event_sink(Box::new(move |event| match sink_events.upgrade() {
+            Some(events) => {
+                events.borrow_mut().push_back(event.clone());
+                true
+            }
+            None => false,
+        }));
+        Ok(Self {
+            source: Self::generic(&service)?,
+            timer: None,
+            connection_count: service.connection_count(),
+            service: Rc::new(service),
+            events,
+        })
+    }
+
+    /// Returns the InputService to send requests from the callbacks of the loop
+    pub fn service(&self) -> Rc<InputService> {
+        self.service.clone()
+    }
+
+    /// Sends the key and repeats it with a calloop timer until it is released
+    ///
+    /// The key is sent as a press followed by a release, so the client does not start its own repetition.
+    /// It is sent again after the `delay` and then every `interval`
+    pub fn repeat_key<Data>(
+        &self,
+        handle: &LoopHandle<'_, Data>,
+        keycode: KeyCode,
+        delay: Duration,
+        interval: Duration,
+    ) -> calloop::Result<HeldKey> {
+        tap_key(&self.service, keycode);
+        let service = self.service.clone();
+        let held = Rc::new(Cell::new(true));
+        let timer_held = held.clone();
+        let token = handle.insert_source(Timer::from_duration(delay), move |_, _, _| {
+            if !timer_held.get() {
+                return TimeoutAction::Drop;
+            }
+            tap_key(&service, keycode);
+            TimeoutAction::ToDuration(interval)
+        })?;
+        Ok(HeldKey {
+            token,
+            keycode,
+            service: self.service.clone(),
+            held,
+            long_pressed: None,
+        })
+    }
+
+    /// Holds the key and calls `on_long_press` with a calloop timer if it is not released before the `delay`
+    ///
+    /// The key is only sent if it is released before the `delay`.
+    /// Otherwise the long press replaces the key
+    pub fn long_press_key<Data, F>(
+        &self,
+        handle: &LoopHandle<'_, Data>,
+        keycode: KeyCode,
+        delay: Duration,
+        on_long_press: F,
+    ) -> calloop::Result<HeldKey>
+    where
+        F: FnOnce(KeyCode, &mut Data) + 'static,
+    {
+        let long_pressed = Rc::new(Cell::new(false));
+        let timer_long_pressed = long_pressed.clone();
+        let held = Rc::new(Cell::new(true));
+        let timer_held = held.clone();
+        let mut on_long_press = Some(on_long_press);
+        let token = handle.insert_source(Timer::from_duration(delay), move |_, _, data| {
+            if !timer_held.get() {
+                return TimeoutAction::Drop;
+            }
+            timer_long_pressed.set(true);
+            if let Some(on_long_press) = on_long_press.take() {
+                on_long_press(keycode, data);
+            }
+            TimeoutAction::Drop
+        })?;
+        Ok(HeldKey {
+            token,
+            keycode,
+            service: self.service.clone(),
+            held,
+            long_pressed: Some(long_pressed),
+        })
+    }
+
+    /// Registers a duplicate of the current connection fd
+    fn generic(service: &InputService) -> io::Result<Generic<File>> {
+        let file = connection::duplicate_fd(service.connection_fd())?;
+        Ok(Generic::new(file, Interest::READ, Mode::Level))
+    }
+
+    /// Dispatches the events that were already read and passes them to the callback
+    fn dispatch<F>(&mut self, mut callback: F) -> io::Result<()>
+    where
+        F: FnMut(IMEvent, &mut ()),
+    {
+        self.service.dispatch_pending()?;
+        // The callback can cause new events to be queued, so the queue must not be borrowed while it runs
+        loop {
+            let event = self.events.borrow_mut().pop_front();
+            match event {
+                Some(event) => callback(event, &mut ()),
+                None => break,
+            }
+        }
+        match self.service.flush() {
+            // The remaining requests get sent with the next flush
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+}
+
+impl EventSource for InputSource {
+    type Event = IMEvent;
+    type Metadata = ();
+    type Ret = ();
+    type Error = io::Error;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        callback: F,
+    ) -> Result<PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let service = &self.service;
+        match &mut self.timer {
+            // The attempt is made by dispatching
+            Some(timer) => {
+                timer.process_events(readiness, token, |_, _| TimeoutAction::Drop)?;
+            }
+            None => {
+                self.source.process_events(readiness, token, |_, _| {
+                    // The lost connection is not read while the InputService reconnects
+                    if service.reconnect_deadline().is_some() {
+                        return Ok(PostAction::Continue);
+                    }
+                    if let Some(read_guard) = service.prepare_read() {
+                        match read_guard.read_events() {
+                            Err(err) if err.kind() != io::ErrorKind::WouldBlock => {
+                                service.connection_lost(err)?;
+                            }
+                            _ => (),
+                        }
+                    }
+                    Ok(PostAction::Continue)
+                })?;
+            }
+        }
+        self.dispatch(callback)?;
+        if self.service.reconnect_deadline().is_some()
+            || self.connection_count != self.service.connection_count()
+        {
+            // The timer of the next attempt or the new connection fd has to be registered
+            return Ok(PostAction::Reregister);
+        }
+        Ok(PostAction::Continue)
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        match self.service.reconnect_deadline() {
+            Some(deadline) => {
+                let mut timer = Timer::from_deadline(deadline);
+                timer.register(poll, token_factory)?;
+                self.timer = Some(timer);
+                Ok(())
+            }
+            None => self.source.register(poll, token_factory),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> calloop::Result<()> {
+        let connection_count = self.service.connection_count();
+        let reconnecting = self.service.reconnect_deadline().is_some();
+        if self.connection_count == connection_count && !reconnecting && self.timer.is_none() {
+            return self.source.reregister(poll, token_factory);
+        }
+        self.unregister(poll)?;
+        if self.connection_count != connection_count {
+            self.source = Self::generic(&self.service)?;
+            self.connection_count = connection_count;
+        }
+        self.register(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+        match self.timer.take() {
+            Some(mut timer) => timer.unregister(poll),
+            None => self.source.unregister(poll),
+        }
+    }
+
+    fn pre_run<F>(&mut self, callback: F) -> calloop::Result<()>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        // Events that were read by someone else would otherwise wait until the fd becomes readable again
+        Ok(self.dispatch(callback)?)
+    }
+}
+
+/// A key that is held down with a calloop timer
+///
+/// Dropping it without calling `release` stops the timer the next time it fires, without sending the key
+#[derive(Debug)]
+#[must_use = "The key is held until it is released"]
+pub struct HeldKey {
+    token: RegistrationToken,
+    keycode: KeyCode,
+    service: Rc<InputService>,
+    /// Set to false when the HeldKey is dropped, so the timer removes itself
+    held: Rc<Cell<bool>>,
+    /// Only set for long presses. True if the timer already fired
+    long_pressed: Option<Rc<Cell<bool>>>,
+}
+
+impl HeldKey {
+    /// Releases the key and removes its timer from the loop
+    ///
+    /// A long press that did not fire yet sends the key instead
+    pub fn release<Data>(self, handle: &LoopHandle<'_, Data>) {
+        handle.remove(self.token);
+        if let Some(long_pressed) = &self.long_pressed {
+            if !long_pressed.get() {
+                tap_key(&self.service, self.keycode);
+            }
+        }
+    }
+}
+
+impl Drop for HeldKey {
+    fn drop(&mut self) {
+        self.held.set(false);
+    }
+}
+
+/// Sends a press and a release of the key and flushes them
+fn tap_key(service: &InputService, keycode: KeyCode) {
+    // The errors are ignored, so a missing virtual keyboard does not stop a repeating timer
+    let _ = service.send_key(keycode, KeyState::Pressed);
+    let _ = service.send_key(keycode, KeyState::Released);
+    let _ = service.flush();
+}
@@ -0,0 +1,74 @@
+use crate::ConnectError;
+use std::ffi::OsString;
+#[cfg(any(feature = "tokio", feature = "calloop"))]
+use std::fs::File;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use wayland_client::Display;
+
+/// Determines how the InputService connects to the wayland server
+#[derive(Debug, Default)]
+pub enum ConnectionTarget {
+    /// Connect to the socket in `WAYLAND_SOCKET` or to the socket named by `WAYLAND_DISPLAY` in `XDG_RUNTIME_DIR`
+    #[default]
+    Env,
+    /// Connect to the socket with this name in `XDG_RUNTIME_DIR`
+    Name(OsString),
+    /// Connect to the socket at this path
+    Path(PathBuf),
+    /// Use a socket that is already connected to the wayland server
+    ///
+    /// The socket can only be used once, so reconnecting is not possible
+    Stream(UnixStream),
+}
+
+impl From<UnixStream> for ConnectionTarget {
+    fn from(stream: UnixStream) -> Self {
+        ConnectionTarget::Stream(stream)
+    }
+}
+
+impl FromRawFd for ConnectionTarget {
+    /// Uses the file descriptor of a socket that is already connected to the wayland server
+    ///
+    /// The ConnectionTarget takes ownership of the file descriptor
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        ConnectionTarget::Stream(UnixStream::from_raw_fd(fd))
+    }
+}
+
+/// Connects to the wayland server
+///
+/// An already connected socket can only be used once, so it is taken out of the `target`
+pub(crate) fn connect(target: &mut Option<ConnectionTarget>) -> Result<Display, ConnectError> {
+    let display = match target {
+        Some(ConnectionTarget::Env) => Display::connect_to_env(),
+        Some(ConnectionTarget::Name(name)) => Display::connect_to_name(name.clone()),
+        Some(ConnectionTarget::Path(path)) => {
+            let stream = UnixStream::connect(path).map_err(ConnectError::Socket)?;
+            unsafe { Display::from_fd(stream.into_raw_fd()) }
+        }
+        Some(ConnectionTarget::Stream(_)) => match target.take() {
+            Some(ConnectionTarget::Stream(stream)) => unsafe {
+                Display::from_fd(stream.into_raw_fd())
+            },
+            _ => unreachable!(),
+        },
+        // The socket was already used by a previous connection
+        None => Err(wayland_client::ConnectError::InvalidFd),
+    };
+    display.map_err(ConnectError::NoDisplay)
+}
+
+/// Duplicates the connection fd so an event loop can own its registration
+///
+/// The duplicate stays valid even if the InputService closes the connection
+#[cfg(any(feature = "tokio", feature = "calloop"))]
+pub(crate) fn duplicate_fd(connection_fd: RawFd) -> std::io::Result<File> {
+    let fd = unsafe { libc::fcntl(connection_fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
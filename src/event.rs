@@ -0,0 +1,91 @@
+use crate::{IMConnector, Rectangle};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_v2::Event;
+use wayland_protocols::unstable::text_input::v3::client::zwp_text_input_v3::{
+    ChangeCause, ContentHint, ContentPurpose,
+};
+
+/// An event the compositor sent to the input method
+///
+/// More events can be added with new versions of the protocols.
+/// The events of keyboard grabs are not included. They are only passed to the KeyboardGrabHandler given to `InputService::grab_keyboard`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum IMEvent {
+    /// A text input was focused and the input method should be shown
+    Activate,
+    /// The text input lost focus and the input method should be hidden
+    Deactivate,
+    /// The text around the cursor. The cursor and the anchor are byte offsets into the text
+    SurroundingText {
+        text: String,
+        cursor: usize,
+        anchor: usize,
+    },
+    /// The reason the text of the text input changed
+    TextChangeCause(ChangeCause),
+    /// The kind of content the text input expects
+    ContentType {
+        hint: ContentHint,
+        purpose: ContentPurpose,
+    },
+    /// The state sent since the last 'done' is complete and should be applied
+    Done,
+    /// The input method is no longer available because another one was bound
+    Unavailable,
+    /// The area of the text input moved. It is sent to the popup surfaces of the input method
+    TextInputRectangle(Rectangle),
+}
+
+impl IMEvent {
+    /// Converts the event of the protocol. Returns None for unknown events
+    pub(crate) fn from_protocol(event: Event) -> Option<Self> {
+        Some(match event {
+            Event::Activate => IMEvent::Activate,
+            Event::Deactivate => IMEvent::Deactivate,
+            Event::SurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => IMEvent::SurroundingText {
+                text,
+                cursor: cursor as usize,
+                anchor: anchor as usize,
+            },
+            Event::TextChangeCause { cause } => IMEvent::TextChangeCause(cause),
+            Event::ContentType { hint, purpose } => IMEvent::ContentType { hint, purpose },
+            Event::Done => IMEvent::Done,
+            Event::Unavailable => IMEvent::Unavailable,
+            _ => return None,
+        })
+    }
+}
+
+/// Receives a copy of every event. Returns false if it no longer wants to receive events
+pub(crate) type EventSink = Box<dyn FnMut(&IMEvent) -> bool>;
+/// The sinks are shared with the filter of the input method, so they survive when it gets created again
+pub(crate) type EventSinks = Rc<RefCell<Vec<EventSink>>>;
+
+/// Passes the event to all sinks and drops the ones that no longer want to receive events
+pub(crate) fn emit(sinks: &EventSinks, event: &IMEvent) {
+    sinks.borrow_mut().retain_mut(|sink| sink(event));
+}
+
+/// Calls the method of the connector that corresponds to the event
+pub(crate) fn notify(connector: &dyn IMConnector, event: IMEvent) {
+    match event {
+        IMEvent::Activate => connector.activated(),
+        IMEvent::Deactivate => connector.deactivated(),
+        IMEvent::SurroundingText {
+            text,
+            cursor,
+            anchor,
+        } => connector.surrounding_text(text, cursor, anchor),
+        IMEvent::TextChangeCause(cause) => connector.text_change_cause(cause),
+        IMEvent::ContentType { hint, purpose } => connector.content_type(hint, purpose),
+        IMEvent::Done => connector.done(),
+        IMEvent::Unavailable => connector.unavailable(),
+        IMEvent::TextInputRectangle(rectangle) => connector.text_input_rectangle(rectangle),
+    }
+}
@@ -0,0 +1,125 @@
+use crate::InputService;
+use glib::thread_guard::ThreadGuard;
+use glib::{Continue, IOCondition, MainContext, Source};
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::rc::{Rc, Weak};
+use std::time::Instant;
+
+/// Dispatches the events of an InputService from a glib MainContext
+///
+/// The IMConnector gets notified on the thread that iterates the MainContext.
+/// Requests are sent after events were dispatched. To send them right away, call `flush` on the InputService
+#[derive(Debug)]
+pub struct GlibInputService {
+    state: Rc<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    service: Rc<InputService>,
+    context: MainContext,
+    /// The source of the current connection or the timeout of the next attempt to reconnect. It gets replaced when the InputService reconnects
+    source: RefCell<Option<Source>>,
+    /// The connection the source belongs to
+    connection_count: Cell<u32>,
+}
+
+impl GlibInputService {
+    /// Attaches the connection fd of the InputService as a source to the `context`
+    ///
+    /// The `context` has to be iterated on the current thread, otherwise dispatching panics.
+    /// Returns an error if the InputService was created on an existing connection
+    pub fn attach(service: InputService, context: &MainContext) -> io::Result<Self> {
+        service.require_own_event_queue()?;
+        let state = Rc::new(State {
+            connection_count: Cell::new(service.connection_count()),
+            service: Rc::new(service),
+            context: context.clone(),
+            source: RefCell::new(None),
+        });
+        // Send the requests of the IMConnector that was set up before
+        let _ = state.service.flush();
+        attach_source(&state);
+        Ok(Self { state })
+    }
+
+    /// Returns the InputService to send requests from the callbacks of the MainContext
+    pub fn service(&self) -> Rc<InputService> {
+        self.state.service.clone()
+    }
+}
+
+impl Drop for GlibInputService {
+    fn drop(&mut self) {
+        if let Some(source) = self.state.source.borrow_mut().take() {
+            source.destroy();
+        }
+    }
+}
+
+/// Creates a source for the current connection fd and attaches it to the context
+///
+/// While the InputService reconnects, a timeout for the next attempt is attached instead
+fn attach_source(state: &Rc<State>) {
+    // The source could be dispatched on another thread, but the InputService must stay on this one
+    let weak_state = ThreadGuard::new(Rc::downgrade(state));
+    let callback = move || match Weak::upgrade(weak_state.get_ref()) {
+        Some(state) => Continue(dispatch(&state)),
+        None => Continue(false),
+    };
+    let source = match state.service.reconnect_deadline() {
+        Some(deadline) => glib::timeout_source_new(
+            deadline.saturating_duration_since(Instant::now()),
+            Some("wayland_input"),
+            glib::PRIORITY_DEFAULT,
+            callback,
+        ),
+        None => glib::source::unix_fd_source_new(
+            state.service.connection_fd(),
+            IOCondition::IN | IOCondition::ERR | IOCondition::HUP,
+            Some("wayland_input"),
+            glib::PRIORITY_DEFAULT,
+            move |_, _| callback(),
+        ),
+    };
+    source.attach(Some(&state.context));
+    *state.source.borrow_mut() = Some(source);
+}
+
+/// Reads and dispatches the events. Returns false if the source has to be removed
+fn dispatch(state: &Rc<State>) -> bool {
+    // The lost connection is not read while the InputService reconnects
+    let read_guard = match state.service.reconnect_deadline() {
+        Some(_) => None,
+        None => state.service.prepare_read(),
+    };
+    if let Some(read_guard) = read_guard {
+        if let Err(err) = read_guard.read_events() {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                if let Err(_err) = state.service.connection_lost(err) {
+                    #[cfg(feature = "debug")]
+                    info!("The connection to the wayland server was lost: {}", _err);
+                    state.source.borrow_mut().take();
+                    return false;
+                }
+            }
+        }
+    }
+    if let Err(_err) = state.service.dispatch_pending() {
+        #[cfg(feature = "debug")]
+        info!("The connection to the wayland server was lost: {}", _err);
+        state.source.borrow_mut().take();
+        return false;
+    }
+    let _ = state.service.flush();
+    let connection_count = state.service.connection_count();
+    let reconnecting = state.service.reconnect_deadline().is_some();
+    if reconnecting || state.connection_count.get() != connection_count {
+        // The source is replaced by a timeout for the next attempt or by one for the new connection fd
+        state.connection_count.set(connection_count);
+        attach_source(state);
+        return false;
+    }
+    true
+}
@@ -0,0 +1,377 @@
+use crate::event::EventSinks;
+use crate::handler::Handler;
+use crate::im_state::ImState;
+use crate::seat::{bind_seat, SeatInfo, SeatSelector, TrackedSeat};
+use crate::versions::{IM_MANAGER_VERSIONS, SEAT_VERSIONS, VK_MANAGER_VERSIONS};
+use crate::{IMConnector, InputService, ProtocolVersions};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_seat::{self, WlSeat};
+use wayland_client::{Attached, GlobalEvent, Interface, Main};
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2;
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_v2::ZwpInputMethodV2;
+use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+
+/// A change of the globals the InputService uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlobalChange {
+    /// A seat was selected. The input method and the virtual keyboard get created for it
+    SeatSelected(SeatInfo),
+    /// The selected seat was removed. The input method and the virtual keyboard were destroyed
+    SeatRemoved(SeatInfo),
+    /// The input method was created
+    InputMethodAdded,
+    /// The input method was destroyed because the zwp_input_method_manager_v2 or the seat was removed
+    InputMethodRemoved,
+    /// The virtual keyboard was created
+    VirtualKeyboardAdded,
+    /// The virtual keyboard was destroyed because the zwp_virtual_keyboard_manager_v1 or the seat was removed
+    VirtualKeyboardRemoved,
+}
+
+/// Callback that gets notified about changes of the globals
+type ListenerCallback = Option<Box<dyn FnMut(GlobalChange)>>;
+pub(crate) type GlobalListener = Arc<Mutex<ListenerCallback>>;
+
+/// The seat the input method and the virtual keyboard are created for
+pub(crate) struct SelectedSeat {
+    pub seat: WlSeat,
+    /// Information about the seat. It is None if the seat belongs to the application
+    pub info: Option<Arc<Mutex<SeatInfo>>>,
+}
+
+/// Keeps track of the globals and the objects that were created from them
+///
+/// The input method and the virtual keyboard get created as soon as a matching seat and their manager are available and they get destroyed when one of them is removed
+#[allow(clippy::type_complexity)]
+pub(crate) struct Globals {
+    seat_selector: SeatSelector,
+    connector: Rc<dyn IMConnector>,
+    /// The keymap that gets uploaded for every new virtual keyboard
+    keymap: String,
+    pub event_sinks: EventSinks,
+    /// Gets passed to every new input method
+    pub handler: Option<Handler>,
+    pub chunk_commit_strings: bool,
+    pub input_method_enabled: bool,
+    pub virtual_keyboard_enabled: bool,
+    /// All wl_seats the compositor advertised
+    pub seats: Vec<TrackedSeat>,
+    pub seat: Option<SelectedSeat>,
+    im_mgr: Option<(u32, Attached<ZwpInputMethodManagerV2>)>,
+    vk_mgr: Option<(u32, Attached<ZwpVirtualKeyboardManagerV1>)>,
+    pub im: Option<(Main<ZwpInputMethodV2>, Arc<Mutex<ImState>>)>,
+    pub vk: Option<(Main<ZwpVirtualKeyboardV1>, Instant)>,
+    /// The modifiers that were last sent by the virtual keyboard
+    pub modifiers: [u32; 4],
+    /// Changes that were not yet delivered to the GlobalListener
+    changes: Vec<GlobalChange>,
+}
+
+impl std::fmt::Debug for Globals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Globals")
+            .field("seat_selector", &self.seat_selector)
+            .field("input_method_enabled", &self.input_method_enabled)
+            .field("virtual_keyboard_enabled", &self.virtual_keyboard_enabled)
+            .field("seats", &self.seats)
+            .field("im_mgr", &self.im_mgr)
+            .field("vk_mgr", &self.vk_mgr)
+            .field("im", &self.im)
+            .field("vk", &self.vk)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Globals {
+    pub fn new(
+        seat_selector: SeatSelector,
+        connector: Rc<dyn IMConnector>,
+        keymap: String,
+    ) -> Self {
+        Self {
+            seat_selector,
+            connector,
+            keymap,
+            event_sinks: EventSinks::default(),
+            handler: None,
+            chunk_commit_strings: true,
+            input_method_enabled: true,
+            virtual_keyboard_enabled: true,
+            seats: Vec::new(),
+            seat: None,
+            im_mgr: None,
+            vk_mgr: None,
+            im: None,
+            vk: None,
+            modifiers: [0; 4],
+            changes: Vec::new(),
+        }
+    }
+
+    /// Uses the managers and the seat of the application instead of binding them
+    pub fn set_external(
+        &mut self,
+        seat: &WlSeat,
+        im_mgr: Option<(u32, Attached<ZwpInputMethodManagerV2>)>,
+        vk_mgr: Option<(u32, Attached<ZwpVirtualKeyboardManagerV1>)>,
+    ) {
+        self.seat = Some(SelectedSeat {
+            seat: seat.clone(),
+            info: None,
+        });
+        self.im_mgr = im_mgr.filter(|_| self.input_method_enabled);
+        self.vk_mgr = vk_mgr.filter(|_| self.virtual_keyboard_enabled);
+        self.create_im();
+        self.create_vk();
+    }
+
+    /// Returns the connector that gets notified about the events of the input method
+    pub fn connector(&self) -> Rc<dyn IMConnector> {
+        self.connector.clone()
+    }
+
+    /// Forgets all bound globals and the objects that were created from them
+    ///
+    /// This is needed after the connection to the wayland server was lost, because all of them are dead
+    pub fn reset(&mut self) {
+        self.destroy_im();
+        self.destroy_vk();
+        if let Some(seat_info) = self.seat.take().and_then(|selected| selected.info) {
+            let seat_info = seat_info.lock().unwrap().clone();
+            self.changes.push(GlobalChange::SeatRemoved(seat_info));
+        }
+        self.seats.clear();
+        self.im_mgr = None;
+        self.vk_mgr = None;
+    }
+
+    /// Returns the versions of the bound globals
+    pub fn versions(&self) -> ProtocolVersions {
+        ProtocolVersions {
+            seat: self
+                .seat
+                .as_ref()
+                .map(|selected| selected.seat.as_ref().version()),
+            input_method_manager: self
+                .im_mgr
+                .as_ref()
+                .map(|(_, im_mgr)| im_mgr.as_ref().version()),
+            virtual_keyboard_manager: self
+                .vk_mgr
+                .as_ref()
+                .map(|(_, vk_mgr)| vk_mgr.as_ref().version()),
+        }
+    }
+
+    /// Binds the managers of the input method and the virtual keyboard when they get advertised
+    ///
+    /// Globals whose version is too old are ignored
+    fn add_manager(
+        &mut self,
+        registry: &Attached<WlRegistry>,
+        id: u32,
+        interface: &str,
+        version: u32,
+    ) {
+        if interface == ZwpInputMethodManagerV2::NAME
+            && self.input_method_enabled
+            && self.im_mgr.is_none()
+        {
+            let (min_version, max_version) = IM_MANAGER_VERSIONS;
+            if version >= min_version {
+                let im_mgr = registry.bind::<ZwpInputMethodManagerV2>(version.min(max_version), id);
+                self.im_mgr = Some((id, (*im_mgr).clone()));
+                self.create_im();
+            }
+        } else if interface == ZwpVirtualKeyboardManagerV1::NAME
+            && self.virtual_keyboard_enabled
+            && self.vk_mgr.is_none()
+        {
+            let (min_version, max_version) = VK_MANAGER_VERSIONS;
+            if version >= min_version {
+                let vk_mgr =
+                    registry.bind::<ZwpVirtualKeyboardManagerV1>(version.min(max_version), id);
+                self.vk_mgr = Some((id, (*vk_mgr).clone()));
+                self.create_vk();
+            }
+        }
+    }
+
+    /// Destroys the objects that were created from the removed global
+    fn remove_global(&mut self, id: u32) {
+        if let Some(position) = self
+            .seats
+            .iter()
+            .position(|tracked| tracked.info.lock().unwrap().id == id)
+        {
+            let tracked = self.seats.remove(position);
+            let seat = tracked.seat;
+            let seat_info = tracked.info.lock().unwrap().clone();
+            if seat.as_ref().version() >= wl_seat::REQ_RELEASE_SINCE {
+                seat.release();
+            }
+            let was_selected = self
+                .seat
+                .as_ref()
+                .and_then(|selected| selected.info.as_ref())
+                .map(|info| info.lock().unwrap().id)
+                == Some(id);
+            if was_selected {
+                self.seat = None;
+                self.destroy_im();
+                self.destroy_vk();
+                self.changes.push(GlobalChange::SeatRemoved(seat_info));
+                // Fall back to another seat that matches
+                self.select_seat();
+            }
+        } else if self.im_mgr.as_ref().map(|(im_mgr_id, _)| *im_mgr_id) == Some(id) {
+            if let Some((_, im_mgr)) = self.im_mgr.take() {
+                self.destroy_im();
+                im_mgr.destroy();
+            }
+        } else if self.vk_mgr.as_ref().map(|(vk_mgr_id, _)| *vk_mgr_id) == Some(id) {
+            self.vk_mgr = None;
+            self.destroy_vk();
+        }
+    }
+
+    /// Selects the first seat that matches the SeatSelector if no seat is selected yet
+    fn select_seat(&mut self) {
+        if self.seat.is_some() {
+            return;
+        }
+        let infos: Vec<_> = self.seats.iter().map(TrackedSeat::info).collect();
+        if let Some(position) = self.seat_selector.select(&infos) {
+            let tracked = self.seats[position].clone();
+            #[cfg(feature = "debug")]
+            info!("Selected the seat {:?}", infos[position].0);
+            self.changes
+                .push(GlobalChange::SeatSelected(infos[position].0.clone()));
+            self.seat = Some(SelectedSeat {
+                seat: tracked.seat.detach(),
+                info: Some(tracked.info),
+            });
+            self.create_im();
+            self.create_vk();
+        }
+    }
+
+    /// Creates the input method if the seat and the manager are available
+    fn create_im(&mut self) {
+        if let (None, Some(selected), Some((_, im_mgr))) = (&self.im, &self.seat, &self.im_mgr) {
+            self.im = Some(InputService::new_im(
+                &selected.seat,
+                im_mgr,
+                self.connector.clone(),
+                self.event_sinks.clone(),
+                self.handler.clone(),
+                self.chunk_commit_strings,
+            ));
+            self.changes.push(GlobalChange::InputMethodAdded);
+        }
+    }
+
+    /// Creates the virtual keyboard if the seat and the manager are available
+    fn create_vk(&mut self) {
+        if let (None, Some(selected), Some((_, vk_mgr))) = (&self.vk, &self.seat, &self.vk_mgr) {
+            let (vk, base_time) = InputService::new_vk(&selected.seat, vk_mgr, &self.keymap);
+            // Restore the modifiers of the previous virtual keyboard
+            if self.modifiers != [0; 4] {
+                let [mods_depressed, mods_latched, mods_locked, group] = self.modifiers;
+                vk.modifiers(mods_depressed, mods_latched, mods_locked, group);
+            }
+            self.vk = Some((vk, base_time));
+            self.changes.push(GlobalChange::VirtualKeyboardAdded);
+        }
+    }
+
+    fn destroy_im(&mut self) {
+        if let Some((im, _)) = self.im.take() {
+            if im.as_ref().is_alive() {
+                im.destroy();
+            }
+            self.changes.push(GlobalChange::InputMethodRemoved);
+        }
+    }
+
+    fn destroy_vk(&mut self) {
+        if let Some((vk, _)) = self.vk.take() {
+            if vk.as_ref().is_alive() {
+                vk.destroy();
+            }
+            self.changes.push(GlobalChange::VirtualKeyboardRemoved);
+        }
+    }
+}
+
+/// Delivers the collected changes to the GlobalListener
+///
+/// The Globals must not be locked while the listener gets called, so it is able to use the InputService
+pub(crate) fn notify(globals: &Mutex<Globals>, listener: &GlobalListener) {
+    let changes = std::mem::take(&mut globals.lock().unwrap().changes);
+    if let Some(listener) = listener.lock().unwrap().as_mut() {
+        for change in changes {
+            listener(change);
+        }
+    }
+}
+
+/// Returns the callback for the GlobalManager that binds and removes the globals as they are advertised and removed
+pub(crate) fn global_callback(
+    globals: &Arc<Mutex<Globals>>,
+    listener: &GlobalListener,
+) -> impl FnMut(GlobalEvent, Attached<WlRegistry>, wayland_client::DispatchData) + 'static {
+    // Weak references prevent reference cycles between the objects and their filters
+    let globals = Arc::downgrade(globals);
+    let listener = Arc::downgrade(listener);
+    move |event, registry, _| {
+        let (globals, listener) = match (globals.upgrade(), listener.upgrade()) {
+            (Some(globals), Some(listener)) => (globals, listener),
+            // The InputService was dropped
+            _ => return,
+        };
+        match event {
+            GlobalEvent::New {
+                id,
+                interface,
+                version,
+            } => {
+                let (min_version, max_version) = SEAT_VERSIONS;
+                if interface == WlSeat::NAME && version >= min_version {
+                    // The callback selects the seat once it sent its name and capabilities
+                    let seat = bind_seat(
+                        &registry,
+                        id,
+                        version.min(max_version),
+                        seat_callback(Arc::downgrade(&globals), Arc::downgrade(&listener)),
+                    );
+                    globals.lock().unwrap().seats.push(seat);
+                } else {
+                    globals
+                        .lock()
+                        .unwrap()
+                        .add_manager(&registry, id, &interface, version);
+                }
+            }
+            GlobalEvent::Removed { id, .. } => globals.lock().unwrap().remove_global(id),
+        }
+        notify(&globals, &listener);
+    }
+}
+
+/// Returns the callback that selects the seat once it sent matching information about itself
+fn seat_callback(
+    globals: Weak<Mutex<Globals>>,
+    listener: Weak<Mutex<ListenerCallback>>,
+) -> impl Fn() + 'static {
+    move || {
+        if let (Some(globals), Some(listener)) = (globals.upgrade(), listener.upgrade()) {
+            globals.lock().unwrap().select_seat();
+            notify(&globals, &listener);
+        }
+    }
+}
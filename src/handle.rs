@@ -0,0 +1,234 @@
+use crate::{CommitStatus, ConnectError, InputService, KeyCode, KeyState, SubmitError};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+/// A request that gets executed on the dispatch thread
+type Call = Box<dyn FnOnce(&InputService) + Send>;
+
+/// Handle to an InputService that runs on its own dispatch thread
+///
+/// The handle can be cloned and shared between threads. The requests are forwarded to the dispatch thread, which sends them and waits for the next one.
+/// The dispatch thread stops when the last handle is dropped or the connection to the wayland server is lost
+#[derive(Debug, Clone)]
+pub struct InputHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// It is only None while the last handle gets dropped
+    calls: Mutex<Option<Sender<Call>>>,
+    /// Wakes the dispatch thread up when a call was sent
+    waker: UnixStream,
+    /// Calls from the dispatch thread itself are rejected, because it would wait for its own result
+    dispatch_thread: ThreadId,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // The channel has to be closed before the dispatch thread wakes up, so it notices it
+        self.calls.lock().unwrap().take();
+        let _ = (&self.waker).write(&[0]);
+    }
+}
+
+impl InputHandle {
+    /// Spawns a dispatch thread and creates the InputService on it
+    ///
+    /// The InputService is not Send, so it is created by `build` on the dispatch thread. The IMConnector is notified on that thread as well.
+    /// Returns the error of `build` if the InputService could not be created.
+    /// Returns ConnectError::DispatchThread if the thread could not be started or `build` panicked.
+    ///
+    /// The methods of the handle must not be called on the dispatch thread, for example from the IMConnector, the global listener or `build`.
+    /// They return SubmitError::OnDispatchThread there, because the thread would wait for itself. Use the InputService directly instead
+    pub fn spawn<F>(build: F) -> Result<Self, ConnectError>
+    where
+        F: FnOnce() -> Result<InputService, ConnectError> + Send + 'static,
+    {
+        let (waker, wakee) = UnixStream::pair().map_err(ConnectError::DispatchThread)?;
+        for stream in [&waker, &wakee] {
+            stream
+                .set_nonblocking(true)
+                .map_err(ConnectError::DispatchThread)?;
+        }
+        let (call_sender, call_receiver) = mpsc::channel();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let dispatch_thread = thread::Builder::new()
+            .name("wayland_input".to_string())
+            .spawn(move || match build() {
+                Ok(service) => {
+                    let _ = result_sender.send(Ok(()));
+                    dispatch_loop(&service, &call_receiver, wakee);
+                }
+                Err(err) => {
+                    let _ = result_sender.send(Err(err));
+                }
+            })
+            .map_err(ConnectError::DispatchThread)?
+            .thread()
+            .id();
+        // The thread only ends without a result if `build` panicked
+        result_receiver.recv().unwrap_or_else(|_| {
+            Err(ConnectError::DispatchThread(io::Error::from(
+                io::ErrorKind::Other,
+            )))
+        })?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                calls: Mutex::new(Some(call_sender)),
+                waker,
+                dispatch_thread,
+            }),
+        })
+    }
+
+    /// Sends a 'commit_string' request to the wayland server
+    pub fn commit_string(&self, text: String) -> Result<(), SubmitError> {
+        self.call(move |service| service.commit_string(text))?
+    }
+
+    /// Sends a 'delete_surrounding_text' request to the wayland server
+    pub fn delete_surrounding_text(&self, before: usize, after: usize) -> Result<(), SubmitError> {
+        self.call(move |service| service.delete_surrounding_text(before, after))?
+    }
+
+    /// Deletes up to `count` graphemes before the cursor from the surrounding text
+    pub fn delete_graphemes_before(&self, count: usize) -> Result<(), SubmitError> {
+        self.call(move |service| service.delete_graphemes_before(count))?
+    }
+
+    /// Deletes up to `count` graphemes after the cursor from the surrounding text
+    pub fn delete_graphemes_after(&self, count: usize) -> Result<(), SubmitError> {
+        self.call(move |service| service.delete_graphemes_after(count))?
+    }
+
+    /// Sends a 'set_preedit_string' request to the wayland server
+    pub fn set_preedit(
+        &self,
+        text: String,
+        cursor: Option<(usize, usize)>,
+    ) -> Result<(), SubmitError> {
+        self.call(move |service| service.set_preedit(text, cursor))?
+    }
+
+    /// Sends a 'commit' request to the wayland server
+    pub fn commit(&self) -> Result<CommitStatus, SubmitError> {
+        self.call(|service| service.commit())?
+    }
+
+    /// Destroys the input method
+    pub fn make_unavailable(&self) -> Result<(), SubmitError> {
+        self.call(|service| service.make_unavailable())?
+    }
+
+    /// Sends a key event with the virtual keyboard
+    pub fn send_key(
+        &self,
+        keycode: KeyCode,
+        desired_key_state: KeyState,
+    ) -> Result<(), SubmitError> {
+        self.call(move |service| service.send_key(keycode, desired_key_state))?
+    }
+
+    /// Sets the modifiers of the virtual keyboard
+    pub fn modifiers(
+        &self,
+        mods_depressed: u32,
+        mods_latched: u32,
+        mods_locked: u32,
+        group: u32,
+    ) -> Result<(), SubmitError> {
+        self.call(move |service| {
+            service.modifiers(mods_depressed, mods_latched, mods_locked, group)
+        })?
+    }
+
+    /// Executes `call` on the dispatch thread and waits for its result
+    ///
+    /// Returns SubmitError::NotAlive if the dispatch thread stopped.
+    /// Returns SubmitError::OnDispatchThread if it is called on the dispatch thread, which would wait for its own result
+    fn call<T, F>(&self, call: F) -> Result<T, SubmitError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&InputService) -> T + Send + 'static,
+    {
+        if thread::current().id() == self.inner.dispatch_thread {
+            return Err(SubmitError::OnDispatchThread);
+        }
+        let (result_sender, result_receiver) = mpsc::channel();
+        let call: Call = Box::new(move |service| {
+            let _ = result_sender.send(call(service));
+        });
+        match &*self.inner.calls.lock().unwrap() {
+            Some(calls) => calls.send(call).map_err(|_| SubmitError::NotAlive)?,
+            None => return Err(SubmitError::NotAlive),
+        }
+        // A full socket means the dispatch thread already has to wake up
+        let _ = (&self.inner.waker).write(&[0]);
+        result_receiver.recv().map_err(|_| SubmitError::NotAlive)
+    }
+}
+
+/// Dispatches the events and executes the calls until all handles are dropped or the connection is lost
+fn dispatch_loop(service: &InputService, calls: &Receiver<Call>, mut wakee: UnixStream) {
+    loop {
+        if service.dispatch_pending().is_err() {
+            #[cfg(feature = "debug")]
+            info!("The connection to the wayland server was lost. Stopping the dispatch thread");
+            return;
+        }
+        let wakee_ready = match service.wait_and_read(Some(wakee.as_raw_fd())) {
+            Ok(wakee_ready) => wakee_ready,
+            Err(_) => return,
+        };
+        if wakee_ready {
+            let mut buffer = [0; 64];
+            while matches!(wakee.read(&mut buffer), Ok(read) if read > 0) {}
+            loop {
+                match calls.try_recv() {
+                    Ok(call) => call(service),
+                    Err(TryRecvError::Empty) => break,
+                    // All handles were dropped
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+        }
+    }
+}
+
+/// Blocks until one of the fds is readable or the timeout elapsed and returns which ones are readable
+pub(crate) fn poll_readable<const N: usize>(
+    fds: [RawFd; N],
+    timeout: Option<Duration>,
+) -> io::Result<[bool; N]> {
+    let mut poll_fds = fds.map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    // The timeout is rounded up, so it does not wake up before it elapsed
+    let timeout = match timeout {
+        Some(timeout) => timeout
+            .as_nanos()
+            .div_ceil(1_000_000)
+            .min(libc::c_int::MAX as u128) as libc::c_int,
+        None => -1,
+    };
+    let result = unsafe {
+        libc::poll(
+            poll_fds.as_mut_ptr(),
+            poll_fds.len() as libc::nfds_t,
+            timeout,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Errors and hang ups count as readable, so reading reports them
+    Ok(poll_fds.map(|poll_fd| poll_fd.revents != 0))
+}
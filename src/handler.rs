@@ -0,0 +1,242 @@
+use crate::im_state::{self, ImState, MAX_COMMIT_STRING_LEN};
+use crate::{IMEvent, InputMethodState, InputService, Preedit, SubmitError};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_v2::ZwpInputMethodV2;
+
+/// Trait to handle the events of the input method with state that is owned by the InputService
+///
+/// Unlike the IMConnector, the handler gets mutable access to itself and can respond to the events through the ImContext
+pub trait IMHandler: 'static {
+    /// Gets called for every event of the input method, after the IMConnector was notified
+    fn event(&mut self, context: &mut ImContext, event: IMEvent);
+}
+
+/// A request that was queued by an IMHandler
+#[derive(Debug)]
+enum Request {
+    Preedit(Preedit),
+    CommitString(String),
+    DeleteSurroundingText(usize, usize),
+    Commit,
+}
+
+/// Queues the requests of an IMHandler
+///
+/// The requests are validated when they are queued and sent in the same order after the handler returned
+#[derive(Debug)]
+pub struct ImContext {
+    state: InputMethodState,
+    chunk_commit_strings: bool,
+    /// True if parts of a long text are waiting to be committed when the requests are sent
+    chunks_pending: bool,
+    requests: Vec<Request>,
+}
+
+impl ImContext {
+    /// Creates a context for the state that was applied with the last 'done'
+    pub(crate) fn new(im_state: &ImState) -> Self {
+        Self {
+            state: im_state.state.clone(),
+            chunk_commit_strings: im_state.chunk_commit_strings,
+            chunks_pending: im_state.chunks_pending(),
+            requests: Vec::new(),
+        }
+    }
+
+    /// Returns the state of the text input that was applied with the last 'done'
+    pub fn state(&self) -> &InputMethodState {
+        &self.state
+    }
+
+    /// Queues a 'set_preedit_string' request
+    ///
+    /// Returns SubmitError::InvalidOffset if the cursor is not a valid range of the text.
+    /// Returns SubmitError::ChunksPending while parts of a long text are still waiting to be committed
+    pub fn set_preedit(
+        &mut self,
+        text: String,
+        cursor: Option<(usize, usize)>,
+    ) -> Result<(), SubmitError> {
+        self.check_no_chunks_pending()?;
+        let preedit = Preedit::new(text, cursor)?;
+        self.requests.push(Request::Preedit(preedit));
+        Ok(())
+    }
+
+    /// Queues a 'commit_string' request. Long texts are split like with `InputService::commit_string`
+    ///
+    /// Chunks that are not committed before the text input is deactivated are dropped and passed to `IMConnector::commit_string_dropped`.
+    /// Returns SubmitError::TooLong if the text is too long and chunking is disabled
+    pub fn commit_string(&mut self, text: String) -> Result<(), SubmitError> {
+        im_state::check_commit_string(self.chunk_commit_strings, &text)?;
+        // Only the first chunk of a long text is sent with the next commit
+        self.chunks_pending |= text.len() > MAX_COMMIT_STRING_LEN;
+        self.requests.push(Request::CommitString(text));
+        Ok(())
+    }
+
+    /// Queues a 'delete_surrounding_text' request. The lengths are in bytes
+    ///
+    /// Returns SubmitError::InvalidOffset if the deletion would end inside a UTF-8 sequence of the surrounding text.
+    /// Returns SubmitError::ChunksPending while parts of a long text are still waiting to be committed
+    pub fn delete_surrounding_text(
+        &mut self,
+        before: usize,
+        after: usize,
+    ) -> Result<(), SubmitError> {
+        self.check_no_chunks_pending()?;
+        self.state.surrounding_text.check_deletion(before, after)?;
+        self.requests
+            .push(Request::DeleteSurroundingText(before, after));
+        Ok(())
+    }
+
+    /// Queues a 'commit' request
+    pub fn commit(&mut self) {
+        self.requests.push(Request::Commit);
+    }
+
+    /// Returns SubmitError::ChunksPending if a request would be applied before the chunks that are still waiting
+    fn check_no_chunks_pending(&self) -> Result<(), SubmitError> {
+        match self.chunks_pending {
+            true => Err(SubmitError::ChunksPending),
+            false => Ok(()),
+        }
+    }
+
+    /// Sends the queued requests
+    pub(crate) fn send(self, im: &ZwpInputMethodV2, im_state: &mut ImState) {
+        for request in self.requests {
+            // The requests were validated when they were queued
+            match request {
+                Request::Preedit(preedit) => {
+                    let _ = im_state.send_preedit(im, preedit);
+                }
+                Request::CommitString(text) => {
+                    let _ = im_state.send_commit_string(im, text);
+                }
+                Request::DeleteSurroundingText(before, after) => {
+                    let _ = im_state.send_delete_surrounding_text(im, before, after);
+                }
+                Request::Commit => {
+                    im_state.send_commit(im);
+                }
+            }
+        }
+    }
+}
+
+/// Passes an event to the IMHandler
+type Dispatch = Rc<dyn Fn(&mut ImContext, IMEvent)>;
+
+/// The IMHandler of an InputService with its type erased
+#[derive(Clone)]
+pub(crate) struct Handler {
+    /// The RefCell of the handler, so it can be downcast to its type
+    cell: Rc<dyn Any>,
+    dispatch: Dispatch,
+    /// Events that were dispatched while the handler was borrowed by `InputService::with_handler`
+    missed: Rc<RefCell<VecDeque<IMEvent>>>,
+}
+
+impl std::fmt::Debug for Handler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handler").finish_non_exhaustive()
+    }
+}
+
+impl Handler {
+    pub fn new<H: IMHandler>(handler: H) -> Self {
+        let cell = Rc::new(RefCell::new(handler));
+        let dispatch_cell = cell.clone();
+        let missed = Rc::new(RefCell::new(VecDeque::new()));
+        let dispatch_missed = missed.clone();
+        Self {
+            cell,
+            dispatch: Rc::new(move |context, event| {
+                // The handler is borrowed if the event was dispatched from inside `InputService::with_handler`
+                match dispatch_cell.try_borrow_mut() {
+                    Ok(mut handler) => handler.event(context, event),
+                    Err(_) => dispatch_missed.borrow_mut().push_back(event),
+                }
+            }),
+            missed,
+        }
+    }
+
+    /// Passes the event to the handler
+    pub fn event(&self, context: &mut ImContext, event: IMEvent) {
+        (self.dispatch)(context, event)
+    }
+}
+
+impl InputService {
+    /// Calls `f` with the IMHandler of the InputService
+    ///
+    /// Returns None if the handler is not of the type `H` or if it is called from inside the handler.
+    /// Events that get dispatched inside of `f` are passed to the handler after `f` returned. Their ImContext has the state of that time
+    pub fn with_handler<H: IMHandler, R, F: FnOnce(&mut H) -> R>(&self, f: F) -> Option<R> {
+        let handler = self.globals.lock().unwrap().handler.clone()?;
+        let cell = handler.cell.downcast_ref::<RefCell<H>>()?;
+        let result = f(&mut *cell.try_borrow_mut().ok()?);
+        self.dispatch_missed(&handler);
+        Some(result)
+    }
+
+    /// Passes the events to the handler that were dispatched while it was borrowed
+    fn dispatch_missed(&self, handler: &Handler) {
+        loop {
+            let event = match handler.missed.borrow_mut().pop_front() {
+                Some(event) => event,
+                None => return,
+            };
+            let im = self.globals.lock().unwrap().im.clone();
+            match im {
+                Some((im, im_state)) => {
+                    let mut context = ImContext::new(&im_state.lock().unwrap());
+                    handler.event(&mut context, event);
+                    context.send(&im, &mut im_state.lock().unwrap());
+                }
+                // The input method was destroyed, so the requests of the handler are discarded
+                None => handler.event(&mut ImContext::new(&ImState::default()), event),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<IMEvent>,
+    }
+
+    impl IMHandler for Recorder {
+        fn event(&mut self, _context: &mut ImContext, event: IMEvent) {
+            self.events.push(event);
+        }
+    }
+
+    #[test]
+    fn queues_events_while_borrowed() {
+        let handler = Handler::new(Recorder::default());
+        let mut context = ImContext::new(&ImState::default());
+        handler.event(&mut context, IMEvent::Activate);
+        let cell = handler.cell.downcast_ref::<RefCell<Recorder>>().unwrap();
+        {
+            let _borrowed = cell.borrow_mut();
+            handler.event(&mut context, IMEvent::Done);
+            handler.event(&mut context, IMEvent::Deactivate);
+        }
+        assert_eq!(cell.borrow().events, vec![IMEvent::Activate]);
+        assert_eq!(
+            handler.missed.borrow().iter().cloned().collect::<Vec<_>>(),
+            vec![IMEvent::Done, IMEvent::Deactivate]
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use crate::keyboard_grab;
+use crate::{IMEvent, InputMethodState, Preedit, StateChanges, SubmitError};
+use std::collections::VecDeque;
+use std::num::Wrapping;
+use unicode_segmentation::UnicodeSegmentation;
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2;
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_v2::ZwpInputMethodV2;
+
+/// Maximum length in bytes of the text of a 'commit_string' request. Wayland messages are limited to 4096 bytes
+pub(crate) const MAX_COMMIT_STRING_LEN: usize = 4000;
+
+/// Tells if a commit was sent against the latest state of the text input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitStatus {
+    /// No 'done' was received since the first pending request, so the compositor applies the changes
+    Current,
+    /// A 'done' was received after the first pending request. The changes were made for an older state of the text input
+    Stale,
+}
+
+/// State of the input method that is shared between the InputService and the filter of its events
+#[derive(Debug, Default)]
+pub(crate) struct ImState {
+    /// Number of 'done' events that were received. It is the serial of the next 'commit' request
+    pub done_count: Wrapping<u32>,
+    /// Number of 'done' events that were received when the first request since the last 'commit' was sent
+    pending_since: Option<Wrapping<u32>>,
+    /// Preedit string that gets shown with the next 'commit'
+    pub pending_preedit: Option<Preedit>,
+    /// Preedit string that is shown
+    pub preedit: Option<Preedit>,
+    /// Keyboard grabs that get released when the input method is deactivated
+    pub keyboard_grabs: Vec<ZwpInputMethodKeyboardGrabV2>,
+    /// State of the text input that gets applied with the next 'done'
+    pending_state: InputMethodState,
+    /// State of the text input that was applied with the last 'done'
+    pub state: InputMethodState,
+    /// Number of 'activate' and 'deactivate' events that were received. It identifies the ImSession of an activation
+    pub generation: u64,
+    /// Generation of the last ImSession that was handed out
+    session_started: Option<u64>,
+    /// Parts of long texts that get committed one after another, each after a 'done'
+    queued_chunks: VecDeque<String>,
+    /// If false, texts that are too long for a single 'commit_string' are rejected instead of split
+    pub chunk_commit_strings: bool,
+}
+
+impl ImState {
+    pub fn new(chunk_commit_strings: bool) -> Self {
+        Self {
+            chunk_commit_strings,
+            ..Self::default()
+        }
+    }
+
+    /// Updates the pending state of the text input with the event
+    pub fn event(&mut self, event: &IMEvent) {
+        if matches!(event, IMEvent::Activate | IMEvent::Deactivate) {
+            // The sessions that were handed out become stale right away
+            self.generation += 1;
+        }
+        self.pending_state.update(event);
+    }
+
+    /// Returns true if the state of the current activation was applied
+    pub fn session_active(&self) -> bool {
+        self.state.active && self.session_started == Some(self.generation)
+    }
+
+    /// Returns true once per activation after its state was applied, so a new ImSession is handed out
+    pub fn start_session(&mut self) -> bool {
+        if !self.state.active || self.session_started == Some(self.generation) {
+            return false;
+        }
+        self.session_started = Some(self.generation);
+        true
+    }
+
+    /// Counts the 'done' event, applies the pending state and returns the fields that changed
+    pub fn done(&mut self) -> StateChanges {
+        self.done_count += 1;
+        let changes = self.pending_state.changes(&self.state);
+        self.state = self.pending_state.clone();
+        changes
+    }
+
+    /// Remembers the state of the text input the pending requests were made for
+    pub fn request_sent(&mut self) {
+        if self.pending_since.is_none() {
+            self.pending_since = Some(self.done_count);
+        }
+    }
+
+    /// Applies the pending state after a 'commit' was sent
+    pub fn committed(&mut self) -> CommitStatus {
+        // The preedit string is reset if it was not set again before the commit
+        self.preedit = self.pending_preedit.take();
+        match self.pending_since.take() {
+            Some(done_count) if done_count != self.done_count => CommitStatus::Stale,
+            _ => CommitStatus::Current,
+        }
+    }
+
+    /// Returns the text to send with 'commit_string' now
+    ///
+    /// Texts that are too long are split into chunks. Only the first chunk is returned, the others wait for a 'done' after the next commit.
+    /// If chunks are still waiting, the text is queued behind them and None is returned.
+    /// Returns SubmitError::TooLong if the text is too long and chunking is disabled
+    pub fn queue_commit_string(&mut self, text: String) -> Result<Option<String>, SubmitError> {
+        check_commit_string(self.chunk_commit_strings, &text)?;
+        if self.queued_chunks.is_empty() && text.len() <= MAX_COMMIT_STRING_LEN {
+            return Ok(Some(text));
+        }
+        let waiting = !self.queued_chunks.is_empty();
+        self.queued_chunks.extend(split_text(&text));
+        Ok(match waiting {
+            true => None,
+            false => self.queued_chunks.pop_front(),
+        })
+    }
+
+    /// Returns the next chunk to commit after a 'done', if the previous one was already committed
+    pub fn next_chunk(&mut self) -> Option<String> {
+        match self.pending_since {
+            Some(_) => None,
+            None => self.queued_chunks.pop_front(),
+        }
+    }
+
+    /// Returns true if parts of a long text are still waiting to be committed
+    pub fn chunks_pending(&self) -> bool {
+        !self.queued_chunks.is_empty()
+    }
+
+    /// Returns SubmitError::ChunksPending if a request would be applied before the chunks that are still waiting
+    pub fn check_no_chunks_pending(&self) -> Result<(), SubmitError> {
+        match self.chunks_pending() {
+            true => Err(SubmitError::ChunksPending),
+            false => Ok(()),
+        }
+    }
+
+    /// Sends a 'set_preedit_string' request. The preedit string is shown after the next commit
+    ///
+    /// Returns SubmitError::ChunksPending while parts of a long text are still waiting to be committed
+    pub fn send_preedit(
+        &mut self,
+        im: &ZwpInputMethodV2,
+        preedit: Preedit,
+    ) -> Result<(), SubmitError> {
+        self.check_no_chunks_pending()?;
+        let (cursor_begin, cursor_end) = preedit.protocol_cursor();
+        im.set_preedit_string(preedit.text().to_string(), cursor_begin, cursor_end);
+        self.pending_preedit = Some(preedit);
+        self.request_sent();
+        Ok(())
+    }
+
+    /// Sends a 'commit_string' request or queues the text behind the chunks that are still waiting
+    pub fn send_commit_string(
+        &mut self,
+        im: &ZwpInputMethodV2,
+        text: String,
+    ) -> Result<(), SubmitError> {
+        if let Some(text) = self.queue_commit_string(text)? {
+            #[cfg(feature = "debug")]
+            info!("Commit string '{}'", text);
+            im.commit_string(text);
+            self.request_sent();
+        }
+        Ok(())
+    }
+
+    /// Sends a 'delete_surrounding_text' request
+    ///
+    /// Returns SubmitError::InvalidOffset if the deletion would end inside a UTF-8 sequence of the surrounding text.
+    /// Returns SubmitError::ChunksPending while parts of a long text are still waiting to be committed
+    pub fn send_delete_surrounding_text(
+        &mut self,
+        im: &ZwpInputMethodV2,
+        before: usize,
+        after: usize,
+    ) -> Result<(), SubmitError> {
+        self.check_no_chunks_pending()?;
+        self.state.surrounding_text.check_deletion(before, after)?;
+        im.delete_surrounding_text(before as u32, after as u32);
+        self.request_sent();
+        Ok(())
+    }
+
+    /// Sends a 'commit' request with the number of received 'done' events as the serial
+    pub fn send_commit(&mut self, im: &ZwpInputMethodV2) -> CommitStatus {
+        im.commit(self.done_count.0);
+        self.committed()
+    }
+
+    /// Commits the next chunk of a long text after a 'done'
+    pub fn send_next_chunk(&mut self, im: &ZwpInputMethodV2) {
+        if let Some(chunk) = self.next_chunk() {
+            im.commit_string(chunk);
+            self.request_sent();
+            self.send_commit(im);
+        }
+    }
+
+    /// Forgets the preedit string and releases the keyboard grabs, because the text input lost focus
+    ///
+    /// Returns the text of the chunks that were not committed yet. They are dropped, because they would end up in another text input
+    pub fn deactivated(&mut self) -> Option<String> {
+        self.pending_preedit = None;
+        self.preedit = None;
+        for grab in self.keyboard_grabs.drain(..) {
+            keyboard_grab::release(&grab);
+        }
+        match self.queued_chunks.is_empty() {
+            true => None,
+            false => Some(self.queued_chunks.drain(..).collect()),
+        }
+    }
+
+    /// Remembers the grab to release it on deactivation and forgets the ones that were already released
+    pub fn add_keyboard_grab(&mut self, grab: ZwpInputMethodKeyboardGrabV2) {
+        self.keyboard_grabs.retain(|grab| grab.as_ref().is_alive());
+        self.keyboard_grabs.push(grab);
+    }
+}
+
+/// Returns SubmitError::TooLong if the text does not fit into a single 'commit_string' and chunking is disabled
+pub(crate) fn check_commit_string(
+    chunk_commit_strings: bool,
+    text: &str,
+) -> Result<(), SubmitError> {
+    match !chunk_commit_strings && text.len() > MAX_COMMIT_STRING_LEN {
+        true => Err(SubmitError::TooLong),
+        false => Ok(()),
+    }
+}
+
+/// Splits the text into chunks that fit into a 'commit_string' request
+///
+/// The text is split between graphemes. Only graphemes that are too long themselves are split between chars
+fn split_text(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for grapheme in text.graphemes(true) {
+        if chunk.len() + grapheme.len() > MAX_COMMIT_STRING_LEN && !chunk.is_empty() {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        if grapheme.len() <= MAX_COMMIT_STRING_LEN {
+            chunk.push_str(grapheme);
+            continue;
+        }
+        for char in grapheme.chars() {
+            if chunk.len() + char.len_utf8() > MAX_COMMIT_STRING_LEN {
+                chunks.push(std::mem::take(&mut chunk));
+            }
+            chunk.push(char);
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_text_at_limit() {
+        let text = "a".repeat(MAX_COMMIT_STRING_LEN);
+        assert_eq!(split_text(&text), vec![text.clone()]);
+        let text = "a".repeat(MAX_COMMIT_STRING_LEN + 1);
+        let chunks = split_text(&text);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), MAX_COMMIT_STRING_LEN);
+        assert_eq!(chunks[1], "a");
+    }
+
+    #[test]
+    fn split_text_multibyte_straddling_limit() {
+        // The '€' takes 3 bytes and would end 2 bytes after the limit
+        let text = format!("{}€b", "a".repeat(MAX_COMMIT_STRING_LEN - 1));
+        let chunks = split_text(&text);
+        assert_eq!(
+            chunks,
+            vec!["a".repeat(MAX_COMMIT_STRING_LEN - 1), "€b".to_string()]
+        );
+    }
+
+    #[test]
+    fn split_text_keeps_graphemes_together() {
+        let text = format!("{}e\u{301}", "a".repeat(MAX_COMMIT_STRING_LEN - 2));
+        let chunks = split_text(&text);
+        assert_eq!(
+            chunks,
+            vec![
+                "a".repeat(MAX_COMMIT_STRING_LEN - 2),
+                "e\u{301}".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn split_text_oversized_grapheme() {
+        // A single grapheme of 1 + 2 * 2500 bytes
+        let grapheme = format!("e{}", "\u{301}".repeat(2500));
+        let text = format!("ab{}c", grapheme);
+        let chunks = split_text(&text);
+        assert_eq!(chunks.concat(), text);
+        assert_eq!(chunks[0], "ab");
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.len() <= MAX_COMMIT_STRING_LEN));
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[2].ends_with('c'));
+    }
+
+    #[test]
+    fn queue_commit_string_behind_chunks() {
+        let mut im_state = ImState::new(true);
+        let long = "a".repeat(MAX_COMMIT_STRING_LEN * 2);
+        assert_eq!(
+            im_state.queue_commit_string(long).unwrap(),
+            Some("a".repeat(MAX_COMMIT_STRING_LEN))
+        );
+        assert!(im_state.chunks_pending());
+        // Short texts wait behind the chunks
+        assert_eq!(im_state.queue_commit_string("b".to_string()).unwrap(), None);
+        let long = "c".repeat(MAX_COMMIT_STRING_LEN + 1);
+        assert_eq!(im_state.queue_commit_string(long).unwrap(), None);
+        assert_eq!(
+            im_state.queued_chunks,
+            vec![
+                "a".repeat(MAX_COMMIT_STRING_LEN),
+                "b".to_string(),
+                "c".repeat(MAX_COMMIT_STRING_LEN),
+                "c".to_string()
+            ]
+        );
+        assert!(matches!(
+            im_state.check_no_chunks_pending(),
+            Err(SubmitError::ChunksPending)
+        ));
+    }
+
+    #[test]
+    fn queue_commit_string_too_long() {
+        let mut im_state = ImState::new(false);
+        let text = "a".repeat(MAX_COMMIT_STRING_LEN);
+        assert_eq!(
+            im_state.queue_commit_string(text.clone()).unwrap(),
+            Some(text)
+        );
+        assert!(matches!(
+            im_state.queue_commit_string("a".repeat(MAX_COMMIT_STRING_LEN + 1)),
+            Err(SubmitError::TooLong)
+        ));
+        assert!(!im_state.chunks_pending());
+    }
+
+    #[test]
+    fn state_is_applied_on_done() {
+        let mut im_state = ImState::default();
+        im_state.event(&IMEvent::Activate);
+        im_state.event(&IMEvent::SurroundingText {
+            text: "abc".to_string(),
+            cursor: 1,
+            anchor: 1,
+        });
+        // The pending state is not visible before 'done'
+        assert_eq!(im_state.state, InputMethodState::default());
+        assert_eq!(
+            im_state.done(),
+            StateChanges::ACTIVE | StateChanges::SURROUNDING_TEXT
+        );
+        assert!(im_state.state.active);
+        assert_eq!(im_state.state.surrounding_text.text(), "abc");
+        im_state.event(&IMEvent::Deactivate);
+        assert!(im_state.state.active);
+        assert_eq!(
+            im_state.done(),
+            StateChanges::ACTIVE | StateChanges::SURROUNDING_TEXT
+        );
+        assert_eq!(im_state.state, InputMethodState::default());
+    }
+
+    #[test]
+    fn one_session_per_activation() {
+        let mut im_state = ImState::default();
+        im_state.event(&IMEvent::Activate);
+        // No session starts before the state of the activation was applied
+        assert!(!im_state.start_session());
+        assert!(!im_state.session_active());
+        im_state.done();
+        assert!(im_state.start_session());
+        let first_generation = im_state.generation;
+        assert!(im_state.session_active());
+        // Later 'done' events of the same activation do not start another session
+        im_state.done();
+        assert!(!im_state.start_session());
+        im_state.event(&IMEvent::Deactivate);
+        assert!(!im_state.session_active());
+        im_state.done();
+        assert!(!im_state.start_session());
+        im_state.event(&IMEvent::Activate);
+        assert!(!im_state.session_active());
+        assert!(!im_state.start_session());
+        im_state.done();
+        assert!(im_state.start_session());
+        assert!(im_state.session_active());
+        // The session of the first activation stays stale
+        assert_ne!(im_state.generation, first_generation);
+        assert!(!im_state.start_session());
+    }
+
+    #[test]
+    fn commit_after_done_is_stale() {
+        let mut im_state = ImState::default();
+        im_state.request_sent();
+        im_state.done();
+        assert_eq!(im_state.committed(), CommitStatus::Stale);
+        // The next commit is made for the latest state again
+        im_state.request_sent();
+        assert_eq!(im_state.committed(), CommitStatus::Current);
+    }
+
+    #[test]
+    fn commit_without_requests_is_current() {
+        let mut im_state = ImState::default();
+        im_state.done();
+        assert_eq!(im_state.committed(), CommitStatus::Current);
+        im_state.done();
+        im_state.request_sent();
+        assert_eq!(im_state.committed(), CommitStatus::Current);
+    }
+
+    #[test]
+    fn deactivated_returns_dropped_chunks() {
+        let mut im_state = ImState::new(true);
+        im_state
+            .queue_commit_string("a".repeat(MAX_COMMIT_STRING_LEN + 1))
+            .unwrap();
+        im_state.queue_commit_string("b".to_string()).unwrap();
+        assert_eq!(im_state.deactivated(), Some("ab".to_string()));
+        assert!(!im_state.chunks_pending());
+        assert_eq!(im_state.deactivated(), None);
+    }
+
+    #[test]
+    fn next_chunk_waits_for_commit() {
+        let mut im_state = ImState::new(true);
+        im_state
+            .queue_commit_string("a".repeat(MAX_COMMIT_STRING_LEN + 1))
+            .unwrap();
+        im_state.request_sent();
+        assert_eq!(im_state.next_chunk(), None);
+        im_state.committed();
+        assert_eq!(im_state.next_chunk(), Some("a".to_string()));
+        assert!(!im_state.chunks_pending());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{KeyState, KeyboardGrabHandler};
+use wayland_client::protocol::wl_keyboard;
+use wayland_client::Main;
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_keyboard_grab_v2::{
+    Event, ZwpInputMethodKeyboardGrabV2,
+};
+
+/// Grab of the hardware keyboard of the seat
+///
+/// While the grab exists, the key events are sent to the KeyboardGrabHandler instead of the focused client.
+/// The grab is released when it is dropped or when the input method is deactivated
+#[derive(Debug)]
+pub struct KeyboardGrab {
+    grab: Main<ZwpInputMethodKeyboardGrabV2>,
+}
+
+impl KeyboardGrab {
+    /// Assigns the events of the grab to the handler
+    pub(crate) fn new<H: KeyboardGrabHandler + 'static>(
+        grab: Main<ZwpInputMethodKeyboardGrabV2>,
+        handler: H,
+    ) -> Self {
+        grab.quick_assign(move |_, event, _| match event {
+            Event::Keymap { format, fd, size } => handler.keymap(format, fd, size),
+            Event::Key {
+                serial,
+                time,
+                key,
+                state,
+            } => {
+                let state = match state {
+                    wl_keyboard::KeyState::Pressed => KeyState::Pressed,
+                    _ => KeyState::Released,
+                };
+                handler.key(serial, time, key, state)
+            }
+            Event::Modifiers {
+                serial,
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+            } => handler.modifiers(serial, mods_depressed, mods_latched, mods_locked, group),
+            Event::RepeatInfo { rate, delay } => handler.repeat_info(rate, delay),
+            _ => (),
+        });
+        Self { grab }
+    }
+
+    /// Returns true if the grab was not released yet
+    pub fn is_active(&self) -> bool {
+        self.grab.as_ref().is_alive()
+    }
+
+    /// Returns the proxy so the grab can be released when the input method is deactivated
+    pub(crate) fn proxy(&self) -> ZwpInputMethodKeyboardGrabV2 {
+        self.grab.as_ref().clone().into()
+    }
+}
+
+impl Drop for KeyboardGrab {
+    fn drop(&mut self) {
+        release(&self.grab);
+    }
+}
+
+/// Releases the grab if it is still active
+pub(crate) fn release(grab: &ZwpInputMethodKeyboardGrabV2) {
+    if grab.as_ref().is_alive() {
+        grab.release();
+    }
+}
@@ -10,16 +10,18 @@ extern crate log;
 
 use std::convert::{AsRef, TryInto};
 use std::io::{Seek, SeekFrom, Write};
-use std::num::Wrapping;
-use std::os::unix::io::IntoRawFd;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use tempfile::tempfile;
-use wayland_client::{protocol::wl_seat::WlSeat, EventQueue, Filter, Main};
-use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2;
-use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_v2::{
-    Event as InputMethodEvent, ZwpInputMethodV2,
+use wayland_client::{
+    protocol::wl_seat::WlSeat, protocol::wl_surface::WlSurface, Attached, Display, EventQueue,
+    Filter, GlobalManager, Main, QueueToken, ReadEventsGuard,
 };
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2;
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_v2::ZwpInputMethodV2;
 use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
 use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
 
@@ -30,6 +32,80 @@ mod wayland;
 mod traits;
 pub use traits::*;
 
+mod versions;
+pub use versions::ProtocolVersions;
+
+mod seat;
+pub use seat::{SeatInfo, SeatSelector};
+
+mod globals;
+pub use globals::GlobalChange;
+
+mod reconnect;
+use globals::{GlobalListener, Globals};
+use reconnect::PendingReconnect;
+pub use reconnect::ReconnectPolicy;
+
+mod preedit;
+pub use preedit::Preedit;
+
+mod keyboard_grab;
+pub use keyboard_grab::KeyboardGrab;
+
+mod surrounding_text;
+pub use surrounding_text::SurroundingText;
+
+mod state;
+pub use state::{InputMethodState, StateChanges};
+
+mod im_state;
+pub use im_state::CommitStatus;
+use im_state::ImState;
+
+mod handler;
+use handler::Handler;
+pub use handler::{IMHandler, ImContext};
+
+mod session;
+pub use session::ImSession;
+
+mod transaction;
+pub use transaction::Transaction;
+
+mod popup;
+pub use popup::{InputPopupSurface, Rectangle};
+
+mod event;
+use event::EventSink;
+use event::EventSinks;
+pub use event::IMEvent;
+
+#[cfg(feature = "tokio")]
+mod async_service;
+#[cfg(feature = "tokio")]
+pub use async_service::{AsyncInputService, EventStream};
+#[cfg(feature = "calloop")]
+mod calloop_source;
+#[cfg(feature = "calloop")]
+pub use calloop_source::{HeldKey, InputSource};
+#[cfg(feature = "glib")]
+mod glib_source;
+#[cfg(feature = "glib")]
+pub use glib_source::GlibInputService;
+
+mod handle;
+pub use handle::InputHandle;
+
+mod run;
+use run::Stopper;
+pub use run::{ExitReason, StopHandle};
+
+mod connection;
+pub use connection::ConnectionTarget;
+
+mod builder;
+pub use builder::{InputServiceBuilder, Requirement};
+
 pub type KeyCode = u32;
 
 #[derive(Debug, Clone)]
@@ -41,6 +117,98 @@ pub enum SubmitError {
     IMNotAvailable,
     /// The virtual_keyboard protocol is unavailable
     VKNotAvailable,
+    /// An offset does not lie on a character boundary or is out of range
+    InvalidOffset,
+    /// The text does not fit into a single request and chunking is disabled
+    TooLong,
+    /// The ImSession the request was made through is no longer active
+    NotActive,
+    /// Parts of a long text are still waiting to be committed, so the request would be applied before them
+    ChunksPending,
+    /// An InputHandle was used on its own dispatch thread, which would wait for itself
+    OnDispatchThread,
+}
+
+#[derive(Debug)]
+/// Error when connecting to the wayland server
+pub enum ConnectError {
+    /// No connection to a wayland display could be established
+    NoDisplay(wayland_client::ConnectError),
+    /// Connecting to the socket at the path of ConnectionTarget::Path failed
+    Socket(std::io::Error),
+    /// The initial roundtrip to the wayland server failed
+    RoundtripFailed(std::io::Error),
+    /// The compositor does not advertise a wl_seat
+    NoSeat,
+    /// The wl_seat advertised by the compositor is too old. Contains the advertised version
+    ///
+    /// It is no longer returned, because every version of wl_seat can be bound. Seats older than version 2 have no name
+    SeatVersionTooOld(u32),
+    /// None of the wl_seats matches the SeatSelector
+    NoMatchingSeat,
+    /// The input method is required but the compositor does not support it
+    InputMethodUnavailable,
+    /// The virtual keyboard is required but the compositor does not support it
+    VirtualKeyboardUnavailable,
+    /// The wayland server sent a protocol error
+    Protocol(wayland_client::ProtocolError),
+    /// The dispatch thread of an InputHandle could not be started or `build` panicked on it
+    DispatchThread(std::io::Error),
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectError::NoDisplay(err) => {
+                write!(f, "Unable to connect to a wayland display: {err}")
+            }
+            ConnectError::RoundtripFailed(err) => {
+                write!(f, "The roundtrip to the wayland server failed: {err}")
+            }
+            ConnectError::NoSeat => f.write_str("The compositor does not advertise a wl_seat"),
+            ConnectError::NoMatchingSeat => {
+                f.write_str("None of the wl_seats matches the selector")
+            }
+            ConnectError::InputMethodUnavailable => {
+                f.write_str("The compositor does not support the required input method")
+            }
+            ConnectError::VirtualKeyboardUnavailable => {
+                f.write_str("The compositor does not support the required virtual keyboard")
+            }
+            ConnectError::SeatVersionTooOld(version) => {
+                write!(
+                    f,
+                    "The wl_seat advertised by the compositor is too old (version {version})"
+                )
+            }
+            ConnectError::Protocol(err) => {
+                write!(f, "The wayland server sent a protocol error: {err}")
+            }
+            ConnectError::DispatchThread(err) => {
+                write!(f, "The dispatch thread could not be started: {err}")
+            }
+            ConnectError::Socket(err) => {
+                write!(f, "Unable to connect to the wayland socket: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnectError::NoDisplay(err) => Some(err),
+            ConnectError::RoundtripFailed(err) => Some(err),
+            ConnectError::Protocol(err) => Some(err),
+            ConnectError::DispatchThread(err) => Some(err),
+            ConnectError::Socket(err) => Some(err),
+            ConnectError::NoSeat
+            | ConnectError::SeatVersionTooOld(_)
+            | ConnectError::NoMatchingSeat
+            | ConnectError::InputMethodUnavailable
+            | ConnectError::VirtualKeyboardUnavailable => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -58,100 +226,316 @@ mod event_enum {
     );
 }
 
-#[allow(clippy::type_complexity)]
-#[derive(Debug)]
 /// Manages the pending state and the current state of the input method.
 pub struct InputService {
-    event_queue: Arc<Mutex<EventQueue>>,
-    im: Option<(Main<ZwpInputMethodV2>, Arc<Mutex<Wrapping<u32>>>)>,
-    vk: Option<(Main<ZwpVirtualKeyboardV1>, std::time::Instant)>,
+    display: Mutex<Display>,
+    event_queue: Option<Arc<Mutex<EventQueue>>>,
+    globals: Arc<Mutex<Globals>>,
+    global_listener: GlobalListener,
+    /// None if the InputService was created on an existing connection or the socket it was given was already used
+    connection_target: Mutex<Option<ConnectionTarget>>,
+    /// Number of connections that were established. It increases with every reconnect
+    connection_count: Mutex<u32>,
+    /// Gets created with the first StopHandle
+    stopper: Mutex<Option<Stopper>>,
+    log_unhandled_events: bool,
+    reconnect_policy: Option<ReconnectPolicy>,
+    /// Set while the connection is lost and the next attempt to reconnect is waited for
+    pending_reconnect: Mutex<Option<PendingReconnect>>,
+}
+
+impl std::fmt::Debug for InputService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InputService")
+            .field("display", &self.display)
+            .field("event_queue", &self.event_queue)
+            .field("globals", &self.globals)
+            .field("connection_target", &self.connection_target)
+            .field("reconnect_policy", &self.reconnect_policy)
+            .field("pending_reconnect", &self.pending_reconnect)
+            .finish_non_exhaustive()
+    }
 }
 
 impl InputService {
-    pub fn new<C: IMConnector + 'static>(
-        connector: Option<C>, //event_queue: EventQueue,
-                              //seat: &WlSeat,
-                              //im_mgr: Option<(Main<ZwpInputMethodManagerV2>, C)>,
-                              //vk_mgr: Option<Main<ZwpVirtualKeyboardManagerV1>>,
+    /// Connects to the wayland server and creates a new InputService
+    ///
+    /// # Panics
+    ///
+    /// Panics if no connection to the wayland server could be established. Use `try_new` to handle the error instead
+    pub fn new<C: IMConnector + 'static>(connector: Option<C>) -> Self {
+        Self::try_new(connector).expect("Unable to connect to the wayland server")
+    }
+
+    /// Connects to the wayland server and creates a new InputService
+    ///
+    /// Returns an error if no connection to the wayland server could be established or if the compositor does not advertise a wl_seat
+    pub fn try_new<C: IMConnector + 'static>(connector: Option<C>) -> Result<Self, ConnectError> {
+        Self::try_new_on_seat(connector, SeatSelector::First)
+    }
+
+    /// Connects to the wayland server and creates a new InputService for the seat selected by the `seat_selector`
+    ///
+    /// Returns an error if no connection to the wayland server could be established or if no wl_seat matches the selector.
+    /// The globals keep getting tracked afterwards, so the input method and the virtual keyboard get created and destroyed when their globals appear and disappear
+    pub fn try_new_on_seat<C: IMConnector + 'static>(
+        connector: Option<C>,
+        seat_selector: SeatSelector,
+    ) -> Result<Self, ConnectError> {
+        let builder = Self::builder().seat(seat_selector);
+        match connector {
+            Some(connector) => builder.connector(connector),
+            None => builder,
+        }
+        .build()
+    }
+
+    /// Creates a new InputService on top of an existing wayland connection
+    ///
+    /// The input method and the virtual keyboard are created on the event queue of the `token`.
+    /// The application has to dispatch that event queue itself, otherwise the `IMConnector` never gets notified.
+    /// The `global_mgr` needs to have received the list of globals already.
+    /// Globals that appear or disappear later on are not tracked
+    pub fn from_connection<C: IMConnector + 'static>(
+        display: &Display,
+        token: QueueToken,
+        global_mgr: &GlobalManager,
+        seat: &WlSeat,
+        connector: Option<C>,
     ) -> Self {
-        let (event_queue, seat, im_mgr, vk_mgr) = wayland::init_wayland();
-        let im = if let Ok(im_mgr) = im_mgr {
-            #[cfg(feature = "debug")]
-            info!("IM manager was availabe");
-            if let Some(connector) = connector {
-                Some(Self::new_im(&seat, im_mgr, connector))
-            } else {
-                Some(Self::new_im(&seat, im_mgr, DummyConnector::default()))
+        let builder = Self::builder();
+        match connector {
+            Some(connector) => builder.connector(connector),
+            None => builder,
+        }
+        .build_from_connection(display, token, global_mgr, seat)
+        .expect("Building only fails if a protocol is required")
+    }
+
+    /// Returns a builder to configure the protocols, the keymap, the seat and the connector of the InputService
+    pub fn builder() -> InputServiceBuilder {
+        InputServiceBuilder::new()
+    }
+
+    /// Sets the callback that gets notified when the input method, the virtual keyboard or the seat are added or removed
+    ///
+    /// It replaces the previous callback. The callback must not set a new callback itself
+    pub fn set_global_listener<F: FnMut(GlobalChange) + 'static>(&self, listener: F) {
+        *self.global_listener.lock().unwrap() = Some(Box::new(listener));
+    }
+
+    /// Enables or disables reconnecting when the connection to the wayland server is lost
+    ///
+    /// The lost connection is detected by `dispatch_pending` and `sync_eventqueue`. The attempts are made by `dispatch_pending` once `reconnect_deadline` is reached, so it never waits itself.
+    /// A successful attempt binds the seat and the managers again and restores the keymap and the modifiers. Afterwards the `IMConnector` gets notified.
+    /// Reconnecting is not possible if the InputService was created on an existing connection
+    pub fn set_reconnect_policy(&mut self, reconnect_policy: Option<ReconnectPolicy>) {
+        self.reconnect_policy = reconnect_policy;
+    }
+
+    /// Starts reconnecting after the connection to the wayland server was lost
+    ///
+    /// Only the first attempt is scheduled, it is made by `dispatch_pending`.
+    /// Returns the error if no ReconnectPolicy is set or reconnecting is not possible
+    pub(crate) fn connection_lost(&self, err: std::io::Error) -> std::io::Result<u32> {
+        let mut pending_reconnect = self.pending_reconnect.lock().unwrap();
+        if pending_reconnect.is_some() {
+            return Ok(0);
+        }
+        // Reconnecting would not help if the wayland server closed the connection because of a protocol error.
+        // The socket the InputService was given can not be used a second time
+        if self.display.lock().unwrap().protocol_error().is_some()
+            || self.connection_target.lock().unwrap().is_none()
+        {
+            return Err(err);
+        }
+        match self
+            .reconnect_policy
+            .as_ref()
+            .and_then(ReconnectPolicy::start)
+        {
+            Some(pending) => {
+                #[cfg(feature = "debug")]
+                info!(
+                    "The connection to the wayland server was lost: {}. Reconnecting",
+                    err
+                );
+                *pending_reconnect = Some(pending);
+                Ok(0)
             }
-        } else {
-            #[cfg(feature = "debug")]
-            info!("IM manager was NOT availabe");
-            None
-        };
+            None => Err(err),
+        }
+    }
+
+    /// Returns the instant of the next attempt to reconnect, if the connection to the wayland server was lost
+    ///
+    /// The connection fd must not be polled until then. Call `dispatch_pending` once the instant is reached to make the attempt
+    pub fn reconnect_deadline(&self) -> Option<Instant> {
+        self.pending_reconnect
+            .lock()
+            .unwrap()
+            .map(|pending| pending.next_attempt)
+    }
 
-        //let im = im_mgr.map(|(im_mgr, connector)| Self::new_im(&seat, im_mgr, connector));
-        let vk = vk_mgr
-            .map(|vk_mgr| {
+    /// Makes the pending attempt to reconnect if it is due
+    ///
+    /// Returns an error if the attempt failed and the ReconnectPolicy gives up
+    fn continue_reconnect(&self, pending: PendingReconnect) -> std::io::Result<u32> {
+        if Instant::now() < pending.next_attempt {
+            return Ok(0);
+        }
+        // The lock is not held during the attempt, because the IMConnector gets notified
+        match self.reconnect() {
+            Ok(()) => Ok(0),
+            Err(err) => {
                 #[cfg(feature = "debug")]
-                info!("VK manager was availabe");
-                Self::new_vk(&seat, vk_mgr)
-            })
-            .ok();
-
-        Self {
-            event_queue: Arc::new(Mutex::new(event_queue)),
-            im,
-            vk,
+                info!("Reconnecting failed: {}", err);
+                let next = self
+                    .reconnect_policy
+                    .as_ref()
+                    .and_then(|reconnect_policy| reconnect_policy.failed(pending));
+                *self.pending_reconnect.lock().unwrap() = next;
+                match next {
+                    Some(_) => Ok(0),
+                    None => Err(std::io::Error::new(std::io::ErrorKind::NotConnected, err)),
+                }
+            }
+        }
+    }
+
+    /// Reconnects to the wayland server and creates the input method and the virtual keyboard again
+    fn reconnect(&self) -> Result<(), ConnectError> {
+        let display = connection::connect(&mut self.connection_target.lock().unwrap())?;
+        // Forget the objects of the lost connection and the ones of previously failed attempts
+        self.globals.lock().unwrap().reset();
+        let (new_event_queue, _global_mgr) =
+            wayland::init_wayland(display, &self.globals, &self.global_listener)?;
+        *self.display.lock().unwrap() = new_event_queue.display().clone();
+        if let Some(event_queue) = &self.event_queue {
+            *event_queue.lock().unwrap() = new_event_queue;
         }
+        *self.connection_count.lock().unwrap() += 1;
+        *self.pending_reconnect.lock().unwrap() = None;
+        globals::notify(&self.globals, &self.global_listener);
+        let connector = self.globals.lock().unwrap().connector();
+        connector.reconnected();
+        Ok(())
+    }
+
+    /// Returns the information about all wl_seats the compositor advertised
+    ///
+    /// The list is empty if the InputService was created on an existing connection
+    pub fn seats(&self) -> Vec<SeatInfo> {
+        self.globals
+            .lock()
+            .unwrap()
+            .seats
+            .iter()
+            .map(|tracked| tracked.info.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Returns the information about the wl_seat the InputService uses
+    ///
+    /// Returns None if no seat is selected or if the InputService was created on an existing connection
+    pub fn seat_info(&self) -> Option<SeatInfo> {
+        self.globals
+            .lock()
+            .unwrap()
+            .seat
+            .as_ref()
+            .and_then(|selected| selected.info.as_ref())
+            .map(|seat_info| seat_info.lock().unwrap().clone())
+    }
+
+    /// Returns the versions of the protocols that were negotiated with the compositor
+    pub fn protocol_versions(&self) -> ProtocolVersions {
+        self.globals.lock().unwrap().versions()
     }
 
     /// Creates a new IMServiceArc wrapped in Arc<Mutex<Self>>
-    fn new_im<C: IMConnector + 'static>(
+    fn new_im(
         seat: &WlSeat,
-        im_manager: Main<ZwpInputMethodManagerV2>,
-        connector: C,
-    ) -> (Main<ZwpInputMethodV2>, Arc<Mutex<Wrapping<u32>>>) {
+        im_manager: &Attached<ZwpInputMethodManagerV2>,
+        connector: Rc<dyn IMConnector>,
+        event_sinks: EventSinks,
+        handler: Option<Handler>,
+        chunk_commit_strings: bool,
+    ) -> (Main<ZwpInputMethodV2>, Arc<Mutex<ImState>>) {
         // Get ZwpInputMethodV2 from ZwpInputMethodManagerV2
         let im = im_manager.get_input_method(seat);
+        let im_state = Arc::new(Mutex::new(ImState::new(chunk_commit_strings)));
+        let filter_im_state = im_state.clone();
 
         // Assigns a filter to the wayland event queue to handle events for ZwpInputMethodV2
         let filter = Filter::new(move |event, _, _| match event {
-            event_enum::Events::InputMethod { event, .. } => match event {
-                InputMethodEvent::Activate => connector.activated(),
-                InputMethodEvent::Deactivate => connector.deactivated(),
-                InputMethodEvent::SurroundingText {
-                    text,
-                    cursor,
-                    anchor,
-                } => connector.surrounding_text(text, cursor as usize, anchor as usize),
-                InputMethodEvent::TextChangeCause { cause } => connector.text_change_cause(cause),
-                InputMethodEvent::ContentType { hint, purpose } => {
-                    connector.content_type(hint, purpose)
+            event_enum::Events::InputMethod { event, object } => {
+                let event = match IMEvent::from_protocol(event) {
+                    Some(event) => event,
+                    None => return,
+                };
+                let mut im_state = filter_im_state.lock().unwrap();
+                im_state.event(&event);
+                let mut dropped_text = None;
+                let state_change = match event {
+                    IMEvent::Deactivate => {
+                        dropped_text = im_state.deactivated();
+                        None
+                    }
+                    IMEvent::Done => {
+                        let changes = im_state.done();
+                        // The next part of a long text is committed once the compositor applied the previous one
+                        im_state.send_next_chunk(&object);
+                        let session = match im_state.start_session() {
+                            true => Some(ImSession::new(&filter_im_state, &im_state)),
+                            false => None,
+                        };
+                        Some((changes, im_state.state.clone(), session))
+                    }
+                    _ => None,
+                };
+                // The IMConnector could send requests, so the lock must not be held while it is notified
+                drop(im_state);
+                if let Some(text) = dropped_text {
+                    connector.commit_string_dropped(text);
+                }
+                if let Some((changes, state, session)) = state_change {
+                    connector.state_changed(&state, changes);
+                    if let Some(session) = session {
+                        connector.session_started(session);
+                    }
                 }
-                InputMethodEvent::Done => connector.done(),
-                InputMethodEvent::Unavailable => connector.unavailable(),
-                _ => (),
-            },
+                event::emit(&event_sinks, &event);
+                match &handler {
+                    Some(handler) => {
+                        event::notify(connector.as_ref(), event.clone());
+                        let mut context = ImContext::new(&filter_im_state.lock().unwrap());
+                        handler.event(&mut context, event);
+                        context.send(&object, &mut filter_im_state.lock().unwrap());
+                    }
+                    None => event::notify(connector.as_ref(), event),
+                }
+            }
         });
         im.assign(filter);
         #[cfg(feature = "debug")]
         info!("The filter was assigned to Main<ZwpInputMethodV2>");
 
-        let serial = Wrapping(0u32);
         #[cfg(feature = "debug")]
         info!("New IMService was created");
         // Return the wrapped IMServiceArc
-        (im, Arc::new(Mutex::new(serial)))
+        (im, im_state)
     }
 
     /// Creates a new IMServiceArc wrapped in Arc<Mutex<Self>>
     fn new_vk(
         seat: &WlSeat,
-        vk_manager: Main<ZwpVirtualKeyboardManagerV1>,
+        vk_manager: &Attached<ZwpVirtualKeyboardManagerV1>,
+        keymap: &str,
     ) -> (Main<ZwpVirtualKeyboardV1>, Instant) {
         let base_time = Instant::now();
         let vk = vk_manager.create_virtual_keyboard(seat);
-        let (keymap_raw_fd, keymap_size_u32) = Self::default_keymap();
+        let (keymap_raw_fd, keymap_size_u32) = Self::keymap_fd(keymap);
         vk.keymap(1, keymap_raw_fd, keymap_size_u32);
         #[cfg(feature = "debug")]
         info!("New VKService was created");
@@ -159,11 +543,11 @@ impl InputService {
         (vk, base_time)
     }
 
-    /// Creates the default keymap, memmory-maps it and returns the file descripter to it
-    fn default_keymap() -> (i32, u32) {
-        println!("Get default keymap and memory map it");
-        let src = keymap::KEYMAP;
-        let keymap_size = keymap::KEYMAP.len();
+    /// Writes the keymap to a memory-mapped file and returns the file descripter to it
+    fn keymap_fd(src: &str) -> (i32, u32) {
+        #[cfg(feature = "debug")]
+        info!("Memory map the keymap");
+        let keymap_size = src.len();
         let keymap_size_u32: u32 = keymap_size.try_into().unwrap(); // Convert it from usize to u32, panics if it is not possible
         let keymap_size_u64: u64 = keymap_size.try_into().unwrap(); // Convert it from usize to u64, panics if it is not possible
         let mut keymap_file = tempfile().expect("Unable to create tempfile");
@@ -183,19 +567,21 @@ impl InputService {
     /// Sends a 'commit_string' request to the wayland-server
     ///
     /// INPUTS: text -> Text that will be committed
-    /// Wayland messages have a maximum length so the length of the text must not exceed 4000 bytes
+    ///
+    /// Wayland messages have a maximum length. A text longer than 4000 bytes is split into chunks and only the first one is sent now.
+    /// The others are committed one after another, each after the compositor sent a 'done' for the previous commit.
+    /// Texts that are committed in the meantime are queued behind them.
+    /// If the text input is deactivated before all chunks were committed, the rest is dropped and passed to `IMConnector::commit_string_dropped`.
+    /// If chunking is disabled, SubmitError::TooLong is returned instead
     pub fn commit_string(&self, text: String) -> Result<(), SubmitError> {
         #[cfg(feature = "debug")]
         info!("Commit_string method was called");
-        if let Some((im, _)) = &self.im {
+        if let Some((im, im_state)) = &self.globals.lock().unwrap().im {
             // Check if proxy is still alive. If the proxy was dead, the requests would fail silently
             match im.as_ref().is_alive() {
                 true => {
-                    #[cfg(feature = "debug")]
-                    info!("Commit string '{}'", text);
-                    // Send the request to the wayland-server
-                    im.commit_string(text);
-                    Ok(())
+                    // Chunks of a previous text could still be waiting
+                    im_state.lock().unwrap().send_commit_string(im, text)
                 }
                 false => {
                     #[cfg(feature = "debug")]
@@ -214,22 +600,123 @@ impl InputService {
     ///
     /// INPUTS:
     ///
-    /// before -> number of chars to delete from the surrounding_text going left from the cursor
+    /// before -> number of bytes to delete from the surrounding_text going left from the cursor
+    ///
+    /// after  -> number of bytes to delete from the surrounding_text going right from the cursor
     ///
-    /// after  -> number of chars to delete from the surrounding_text going right from the cursor
+    /// Returns SubmitError::InvalidOffset if the deletion would end inside a UTF-8 sequence of the surrounding text.
+    /// Use `delete_graphemes_before` and `delete_graphemes_after` to delete whole characters.
+    /// Returns SubmitError::ChunksPending while parts of a long text are still waiting to be committed
     pub fn delete_surrounding_text(&self, before: usize, after: usize) -> Result<(), SubmitError> {
         #[cfg(feature = "debug")]
         info!(
-            "Send a request to the wayland server to delete {} chars before and {} after the cursor from the surrounding text",
+            "Send a request to the wayland server to delete {} bytes before and {} after the cursor from the surrounding text",
             before, after
         );
-        if let Some((im, _)) = &self.im {
+        if let Some((im, im_state)) = &self.globals.lock().unwrap().im {
+            // Check if proxy is still alive. If the proxy was dead, the requests would fail silently
+            match im.as_ref().is_alive() {
+                true => im_state
+                    .lock()
+                    .unwrap()
+                    .send_delete_surrounding_text(im, before, after),
+                false => Err(SubmitError::NotAlive),
+            }
+        } else {
+            Err(SubmitError::IMNotAvailable)
+        }
+    }
+
+    /// Deletes up to `count` graphemes before the cursor from the surrounding text
+    ///
+    /// The graphemes are counted in the surrounding text that was applied with the last 'done'
+    pub fn delete_graphemes_before(&self, count: usize) -> Result<(), SubmitError> {
+        let before = self
+            .input_method_state()
+            .surrounding_text
+            .graphemes_before_cursor(count);
+        self.delete_surrounding_text(before, 0)
+    }
+
+    /// Deletes up to `count` graphemes after the cursor from the surrounding text
+    ///
+    /// The graphemes are counted in the surrounding text that was applied with the last 'done'
+    pub fn delete_graphemes_after(&self, count: usize) -> Result<(), SubmitError> {
+        let after = self
+            .input_method_state()
+            .surrounding_text
+            .graphemes_after_cursor(count);
+        self.delete_surrounding_text(0, after)
+    }
+
+    /// Sends a 'set_preedit_string' request to the wayland server
+    ///
+    /// INPUTS:
+    ///
+    /// text -> Text that is shown at the cursor until the next commit replaces it
+    ///
+    /// cursor -> Byte offsets of the begin and the end of the cursor in the text. None hides the cursor
+    ///
+    /// Returns SubmitError::InvalidOffset if the cursor is not a valid range of the text.
+    /// Returns SubmitError::ChunksPending while parts of a long text are still waiting to be committed.
+    /// The preedit string gets shown with the next commit and is cleared when the input method is deactivated
+    pub fn set_preedit(
+        &self,
+        text: String,
+        cursor: Option<(usize, usize)>,
+    ) -> Result<(), SubmitError> {
+        let preedit = Preedit::new(text, cursor)?;
+        #[cfg(feature = "debug")]
+        info!("Set the preedit string to {:?}", preedit);
+        if let Some((im, im_state)) = &self.globals.lock().unwrap().im {
+            // Check if proxy is still alive. If the proxy was dead, the requests would fail silently
+            match im.as_ref().is_alive() {
+                true => im_state.lock().unwrap().send_preedit(im, preedit),
+                false => Err(SubmitError::NotAlive),
+            }
+        } else {
+            Err(SubmitError::IMNotAvailable)
+        }
+    }
+
+    /// Creates a popup surface from the `surface` to show for example a list of candidates next to the text input
+    ///
+    /// The `surface` has to belong to the same connection and must not have a role yet.
+    /// The area of the text input is reported to IMConnector::text_input_rectangle
+    pub fn create_popup_surface(
+        &self,
+        surface: &WlSurface,
+    ) -> Result<InputPopupSurface, SubmitError> {
+        let globals = self.globals.lock().unwrap();
+        if let Some((im, _)) = &globals.im {
+            // Check if proxy is still alive. If the proxy was dead, the requests would fail silently
+            match im.as_ref().is_alive() {
+                true => Ok(InputPopupSurface::new(
+                    im.get_input_popup_surface(surface),
+                    globals.connector(),
+                    globals.event_sinks.clone(),
+                )),
+                false => Err(SubmitError::NotAlive),
+            }
+        } else {
+            Err(SubmitError::IMNotAvailable)
+        }
+    }
+
+    /// Grabs the hardware keyboard of the seat, so the key events are sent to the `handler`
+    ///
+    /// The grab is released when the returned KeyboardGrab is dropped or when the input method is deactivated
+    pub fn grab_keyboard<H: KeyboardGrabHandler + 'static>(
+        &self,
+        handler: H,
+    ) -> Result<KeyboardGrab, SubmitError> {
+        if let Some((im, im_state)) = &self.globals.lock().unwrap().im {
             // Check if proxy is still alive. If the proxy was dead, the requests would fail silently
             match im.as_ref().is_alive() {
                 true => {
-                    // Send the delete_surrounding_text request to the wayland-server
-                    im.delete_surrounding_text(before as u32, after as u32);
-                    Ok(())
+                    let grab = KeyboardGrab::new(im.grab_keyboard(), handler);
+                    im_state.lock().unwrap().add_keyboard_grab(grab.proxy());
+                    Ok(grab)
                 }
                 false => Err(SubmitError::NotAlive),
             }
@@ -238,22 +725,37 @@ impl InputService {
         }
     }
 
+    /// Returns the preedit string that is shown or None if there is none
+    pub fn preedit(&self) -> Option<Preedit> {
+        let globals = self.globals.lock().unwrap();
+        let (_, im_state) = globals.im.as_ref()?;
+        let preedit = im_state.lock().unwrap().preedit.clone();
+        preedit
+    }
+
+    /// Returns the state of the text input that was applied with the last 'done'
+    ///
+    /// The state is inactive if there is no input method
+    pub fn input_method_state(&self) -> InputMethodState {
+        let globals = self.globals.lock().unwrap();
+        match &globals.im {
+            Some((_, im_state)) => im_state.lock().unwrap().state.clone(),
+            None => InputMethodState::default(),
+        }
+    }
+
     /// Sends a 'commit' request to the wayland server
     ///
-    /// This makes the pending changes permanent
-    pub fn commit(&self) -> Result<(), SubmitError> {
+    /// This makes the pending changes permanent. The serial is the number of 'done' events that were received.
+    /// Returns CommitStatus::Stale if a 'done' was received after the first pending request.
+    /// The compositor still applies the changes, but they were made for an older state of the text input
+    pub fn commit(&self) -> Result<CommitStatus, SubmitError> {
         #[cfg(feature = "debug")]
         info!("Commit the changes");
-        if let Some((im, serial)) = &self.im {
+        if let Some((im, im_state)) = &self.globals.lock().unwrap().im {
             // Check if proxy is still alive. If the proxy was dead, the requests would fail silently
             match im.as_ref().is_alive() {
-                true => {
-                    // Send request to wayland-server
-                    im.commit(serial.lock().unwrap().0);
-                    // Increase the serial
-                    serial.lock().unwrap().0 += 1;
-                    Ok(())
-                }
+                true => Ok(im_state.lock().unwrap().send_commit(im)),
                 false => Err(SubmitError::NotAlive),
             }
         } else {
@@ -264,7 +766,7 @@ impl InputService {
     pub fn make_unavailable(&self) -> Result<(), SubmitError> {
         #[cfg(feature = "debug")]
         info!("make_unavailable() was called");
-        if let Some((im, _)) = &self.im {
+        if let Some((im, _)) = &self.globals.lock().unwrap().im {
             // Check if proxy is still alive. If the proxy was dead, the requests would fail silently
             match im.as_ref().is_alive() {
                 true => {
@@ -284,7 +786,7 @@ impl InputService {
         keycode: KeyCode,
         desired_key_state: KeyState,
     ) -> Result<(), SubmitError> {
-        if let Some((vk, base_time)) = &self.vk {
+        if let Some((vk, base_time)) = &self.globals.lock().unwrap().vk {
             let time = Self::elapsed_time_millis(base_time);
             #[cfg(feature = "debug")]
             info!("time: {}, keycode: {}", time, keycode);
@@ -308,7 +810,10 @@ impl InputService {
     ) -> Result<(), SubmitError> {
         #[cfg(feature = "debug")]
         info!("Pressed modifiers: {}", mods_depressed);
-        if let Some((vk, _)) = &self.vk {
+        let mut globals = self.globals.lock().unwrap();
+        // Remember the modifiers so they can be restored when the virtual keyboard gets created again
+        globals.modifiers = [mods_depressed, mods_latched, mods_locked, group];
+        if let Some((vk, _)) = &globals.vk {
             if vk.as_ref().is_alive() {
                 vk.modifiers(mods_depressed, mods_latched, mods_locked, group);
                 Ok(())
@@ -332,14 +837,146 @@ impl InputService {
         }
     }
 
+    /// Sends the pending requests to the wayland server and dispatches the received events
+    ///
+    /// If the InputService was created on an existing connection, the application dispatches the events itself and the requests are only flushed
+    ///
+    /// # Panics
+    ///
+    /// Panics if the connection to the wayland server was lost and no `ReconnectPolicy` is set or reconnecting failed
     pub fn sync_eventqueue(&self) {
+        match &self.event_queue {
+            Some(event_queue) => {
+                let result =
+                    event_queue
+                        .lock()
+                        .unwrap()
+                        .sync_roundtrip(&mut (), |raw_event, _, _| {
+                            if self.log_unhandled_events {
+                                println!("Unhandled Event: {raw_event:?}")
+                            }
+                        });
+                if let Err(err) = result {
+                    if let Err(err) = self.connection_lost(err) {
+                        panic!("The connection to the wayland server was lost: {err}");
+                    }
+                    // The roundtrip blocks anyway, so waiting for the attempts is fine here
+                    while let Some(deadline) = self.reconnect_deadline() {
+                        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                        if let Err(err) = self.dispatch_pending() {
+                            panic!("Unable to reconnect to the wayland server: {err}");
+                        }
+                    }
+                }
+            }
+            None => {
+                // The application dispatches its event queue itself. A failed flush gets retried with the next one
+                let _ = self.display.lock().unwrap().flush();
+            }
+        }
+    }
+
+    /// Returns a receiver for the events the compositor sends to the input method
+    ///
+    /// The events are sent while the InputService dispatches, for example in `dispatch` or `run`.
+    /// The IMConnector still gets notified, so the default DummyConnector can be used when the events are handled through the receiver.
+    /// Keyboard grab events are not sent to the receiver, they only reach the KeyboardGrabHandler of the grab
+    pub fn events(&self) -> Receiver<IMEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.add_event_sink(Box::new(move |event| sender.send(event.clone()).is_ok()));
+        receiver
+    }
+
+    /// Adds a sink that receives a copy of every event of the input method
+    pub(crate) fn add_event_sink(&self, sink: EventSink) {
+        self.globals
+            .lock()
+            .unwrap()
+            .event_sinks
+            .borrow_mut()
+            .push(sink);
+    }
+
+    /// Returns the number of connections that were established. It changes when the InputService reconnects
+    #[cfg(any(feature = "tokio", feature = "calloop", feature = "glib"))]
+    pub(crate) fn connection_count(&self) -> u32 {
+        *self.connection_count.lock().unwrap()
+    }
+
+    /// Returns true if the InputService dispatches its own event queue
+    pub(crate) fn owns_event_queue(&self) -> bool {
+        self.event_queue.is_some()
+    }
+
+    /// Returns an error with the kind `InvalidInput` if the InputService was created on an existing connection, because the application dispatches the events itself
+    pub(crate) fn require_own_event_queue(&self) -> std::io::Result<()> {
+        match self.owns_event_queue() {
+            true => Ok(()),
+            false => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The InputService was created on an existing connection",
+            )),
+        }
+    }
+
+    /// Returns the file descriptor of the connection to the wayland server
+    ///
+    /// It becomes readable when the wayland server sent events. It should only be polled and never be read from or written to.
+    /// The file descriptor changes when the InputService reconnects
+    pub fn connection_fd(&self) -> RawFd {
+        self.display.lock().unwrap().get_connection_fd()
+    }
+
+    /// Prepares reading the events from the connection
+    ///
+    /// Returns None if there are still events to be dispatched with `dispatch_pending` or if the InputService was created on an existing connection.
+    /// Otherwise the returned guard reads the events once the connection fd is readable. They are dispatched by the next call of `dispatch_pending`
+    pub fn prepare_read(&self) -> Option<ReadEventsGuard> {
         self.event_queue
+            .as_ref()
+            .and_then(|event_queue| event_queue.lock().unwrap().prepare_read())
+    }
+
+    /// Dispatches the events that were already read without blocking and returns the number of dispatched events
+    ///
+    /// If the InputService was created on an existing connection, the application dispatches the events itself and nothing is done.
+    /// If the connection to the wayland server was lost and a `ReconnectPolicy` is set, the InputService starts reconnecting.
+    /// While it reconnects, nothing is dispatched. Every call makes the next attempt once `reconnect_deadline` is reached and 0 is returned.
+    /// The error is returned if reconnecting was not possible or the policy gave up
+    pub fn dispatch_pending(&self) -> std::io::Result<u32> {
+        let event_queue = match &self.event_queue {
+            Some(event_queue) => event_queue,
+            None => return Ok(0),
+        };
+        let pending_reconnect = *self.pending_reconnect.lock().unwrap();
+        if let Some(pending) = pending_reconnect {
+            return self.continue_reconnect(pending);
+        }
+        let result = event_queue
             .lock()
             .unwrap()
-            .sync_roundtrip(&mut (), |raw_event, _, _| {
-                println!("Unhandled Event: {raw_event:?}")
-            })
-            .unwrap();
+            .dispatch_pending(&mut (), |raw_event, _, _| {
+                if self.log_unhandled_events {
+                    println!("Unhandled Event: {raw_event:?}")
+                }
+            });
+        match result {
+            Err(err) => self.connection_lost(err),
+            dispatched => dispatched,
+        }
+    }
+
+    /// Sends the pending requests to the wayland server without blocking
+    ///
+    /// Returns an error with the kind `WouldBlock` if not all requests could be sent. The fd should then be polled until it is writable
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.display.lock().unwrap().flush()
+    }
+}
+
+impl AsRawFd for InputService {
+    fn as_raw_fd(&self) -> RawFd {
+        self.connection_fd()
     }
 }
 
@@ -0,0 +1,66 @@
+use crate::event::{self, EventSinks};
+use crate::{IMConnector, IMEvent};
+use std::rc::Rc;
+use wayland_client::Main;
+use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_popup_surface_v2::{
+    Event, ZwpInputPopupSurfaceV2,
+};
+
+/// Area of the text input in surface local coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rectangle {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Surface with the 'input_popup' role that the compositor places next to the text input
+///
+/// The popup surface is destroyed when it is dropped or when the input method is destroyed
+#[derive(Debug)]
+pub struct InputPopupSurface {
+    popup: Main<ZwpInputPopupSurfaceV2>,
+}
+
+impl InputPopupSurface {
+    /// Assigns the events of the popup surface to the connector and the event sinks
+    pub(crate) fn new(
+        popup: Main<ZwpInputPopupSurfaceV2>,
+        connector: Rc<dyn IMConnector>,
+        event_sinks: EventSinks,
+    ) -> Self {
+        popup.quick_assign(move |_, event, _| {
+            if let Event::TextInputRectangle {
+                x,
+                y,
+                width,
+                height,
+            } = event
+            {
+                let event = IMEvent::TextInputRectangle(Rectangle {
+                    x,
+                    y,
+                    width,
+                    height,
+                });
+                event::emit(&event_sinks, &event);
+                event::notify(connector.as_ref(), event);
+            }
+        });
+        Self { popup }
+    }
+
+    /// Returns true if the popup surface was not destroyed together with the input method
+    pub fn is_alive(&self) -> bool {
+        self.popup.as_ref().is_alive()
+    }
+}
+
+impl Drop for InputPopupSurface {
+    fn drop(&mut self) {
+        if self.popup.as_ref().is_alive() {
+            self.popup.destroy();
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::SubmitError;
+use std::convert::TryInto;
+
+/// Text that is shown in the text input while it is being composed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preedit {
+    text: String,
+    cursor: Option<(usize, usize)>,
+}
+
+impl Preedit {
+    /// Creates a preedit string
+    ///
+    /// The cursor is the range between two byte offsets into the text. If it is None, the cursor is hidden.
+    /// Returns SubmitError::InvalidOffset if the range is reversed, exceeds the text or does not start and end on character boundaries
+    pub fn new(text: String, cursor: Option<(usize, usize)>) -> Result<Self, SubmitError> {
+        if let Some((cursor_begin, cursor_end)) = cursor {
+            if cursor_begin > cursor_end
+                || !text.is_char_boundary(cursor_begin)
+                || !text.is_char_boundary(cursor_end)
+                || TryInto::<i32>::try_into(cursor_end).is_err()
+            {
+                return Err(SubmitError::InvalidOffset);
+            }
+        }
+        Ok(Self { text, cursor })
+    }
+
+    /// Returns the text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the byte offsets of the begin and the end of the cursor or None if it is hidden
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor
+    }
+
+    /// Returns the cursor like the protocol expects it. A hidden cursor is -1
+    pub(crate) fn protocol_cursor(&self) -> (i32, i32) {
+        match self.cursor {
+            // The offsets were checked to fit into an i32
+            Some((cursor_begin, cursor_end)) => (cursor_begin as i32, cursor_end as i32),
+            None => (-1, -1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_checks_cursor() {
+        let text = "é€e\u{301}";
+        assert!(Preedit::new(text.to_string(), None).is_ok());
+        assert!(Preedit::new(text.to_string(), Some((0, 0))).is_ok());
+        assert!(Preedit::new(text.to_string(), Some((2, 5))).is_ok());
+        assert!(Preedit::new(text.to_string(), Some((5, 8))).is_ok());
+        // The cursor may lie between a char and its combining mark
+        assert!(Preedit::new(text.to_string(), Some((6, 6))).is_ok());
+        // Inside 'é' and inside '€'
+        assert!(Preedit::new(text.to_string(), Some((1, 2))).is_err());
+        assert!(Preedit::new(text.to_string(), Some((2, 3))).is_err());
+        // Reversed and out of range
+        assert!(Preedit::new(text.to_string(), Some((5, 2))).is_err());
+        assert!(Preedit::new(text.to_string(), Some((0, 9))).is_err());
+    }
+
+    #[test]
+    fn protocol_cursor() {
+        let preedit = Preedit::new("€".to_string(), Some((0, 3))).unwrap();
+        assert_eq!(preedit.protocol_cursor(), (0, 3));
+        let preedit = Preedit::new("€".to_string(), None).unwrap();
+        assert_eq!(preedit.protocol_cursor(), (-1, -1));
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+
+/// Determines how often and how fast the InputService tries to reconnect after the connection to the wayland server was lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Time to wait before the first attempt
+    pub initial_delay: Duration,
+    /// The delay doubles after every failed attempt until it reaches this maximum
+    pub max_delay: Duration,
+    /// Number of attempts before giving up. None means it never gives up
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            max_attempts: None,
+        }
+    }
+}
+
+/// A reconnect that waits for its next attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PendingReconnect {
+    /// Number of attempts that failed
+    failed_attempts: u32,
+    /// Time that was waited for the next attempt
+    delay: Duration,
+    /// The next attempt is made once this instant is reached
+    pub next_attempt: Instant,
+}
+
+impl ReconnectPolicy {
+    /// Schedules the first attempt after the connection was lost
+    ///
+    /// Returns None if the policy allows no attempts
+    pub(crate) fn start(&self) -> Option<PendingReconnect> {
+        if self.max_attempts == Some(0) {
+            return None;
+        }
+        Some(PendingReconnect {
+            failed_attempts: 0,
+            delay: self.initial_delay,
+            next_attempt: Instant::now() + self.initial_delay,
+        })
+    }
+
+    /// Schedules the next attempt after one failed, waiting longer than before
+    ///
+    /// Returns None if the policy gives up
+    pub(crate) fn failed(&self, pending: PendingReconnect) -> Option<PendingReconnect> {
+        let failed_attempts = pending.failed_attempts.saturating_add(1);
+        if matches!(self.max_attempts, Some(max_attempts) if failed_attempts >= max_attempts) {
+            return None;
+        }
+        let delay = pending
+            .delay
+            .checked_mul(2)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        Some(PendingReconnect {
+            failed_attempts,
+            delay,
+            next_attempt: Instant::now() + delay,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_attempts: Option<u32>) -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            max_attempts,
+        }
+    }
+
+    #[test]
+    fn no_attempts() {
+        assert_eq!(policy(Some(0)).start(), None);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let policy = policy(Some(2));
+        let pending = policy.start().unwrap();
+        let pending = policy.failed(pending).unwrap();
+        assert_eq!(policy.failed(pending), None);
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let policy = policy(None);
+        let mut pending = policy.start().unwrap();
+        assert_eq!(pending.delay, Duration::from_millis(100));
+        pending = policy.failed(pending).unwrap();
+        assert_eq!(pending.delay, Duration::from_millis(200));
+        for _ in 0..100 {
+            pending = policy.failed(pending).unwrap();
+        }
+        assert_eq!(pending.delay, Duration::from_millis(300));
+        assert!(pending.next_attempt <= Instant::now() + Duration::from_millis(300));
+    }
+}
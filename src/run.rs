@@ -0,0 +1,214 @@
+use crate::handle::poll_readable;
+use crate::{IMEvent, InputService};
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use wayland_client::ProtocolError;
+
+/// The reason `InputService::run` returned
+#[derive(Debug)]
+pub enum ExitReason {
+    /// The StopHandle was triggered
+    Stopped,
+    /// The compositor sent 'unavailable' because another input method was bound
+    Unavailable,
+    /// The connection to the wayland server was lost and could not be reestablished
+    ConnectionLost(io::Error),
+    /// The wayland server sent a protocol error
+    Protocol(ProtocolError),
+    /// The InputService was created on an existing connection, so the application has to dispatch the events itself
+    ExternalConnection,
+}
+
+/// Stops a running `InputService::run`
+///
+/// The handle can be cloned and triggered from any thread
+#[derive(Debug, Clone)]
+pub struct StopHandle {
+    inner: Arc<StopState>,
+}
+
+#[derive(Debug)]
+struct StopState {
+    stopped: AtomicBool,
+    /// Wakes the run loop up
+    waker: UnixStream,
+}
+
+impl StopHandle {
+    /// Makes `run` return ExitReason::Stopped. If it is not running, the next call returns immediately
+    pub fn stop(&self) {
+        self.inner.stopped.store(true, Ordering::SeqCst);
+        let _ = (&self.inner.waker).write(&[0]);
+    }
+}
+
+/// The end of the StopHandle the run loop polls
+#[derive(Debug)]
+pub(crate) struct Stopper {
+    handle: StopHandle,
+    wakee: UnixStream,
+}
+
+impl Stopper {
+    fn new() -> io::Result<Self> {
+        let (waker, wakee) = UnixStream::pair()?;
+        waker.set_nonblocking(true)?;
+        wakee.set_nonblocking(true)?;
+        Ok(Self {
+            handle: StopHandle {
+                inner: Arc::new(StopState {
+                    stopped: AtomicBool::new(false),
+                    waker,
+                }),
+            },
+            wakee,
+        })
+    }
+
+    /// Returns true once if the StopHandle was triggered
+    fn take_stopped(&self) -> bool {
+        let mut buffer = [0; 64];
+        while matches!((&self.wakee).read(&mut buffer), Ok(read) if read > 0) {}
+        self.handle.inner.stopped.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl InputService {
+    /// Returns a handle to stop `run`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the socket to wake the run loop up could not be created
+    pub fn stop_handle(&self) -> StopHandle {
+        let mut stopper = self.stopper.lock().unwrap();
+        if stopper.is_none() {
+            *stopper = Some(Stopper::new().expect("Unable to create a socket pair"));
+        }
+        stopper.as_ref().unwrap().handle.clone()
+    }
+
+    /// Dispatches the events to the IMConnector until the StopHandle is triggered or the input method can no longer be used
+    ///
+    /// The requests are sent after the events were dispatched.
+    /// If a ReconnectPolicy is set, a lost connection gets reestablished and the loop continues.
+    /// If the InputService was created on an existing connection, the application dispatches the events itself and ExitReason::ExternalConnection is returned right away
+    pub fn run(&self) -> ExitReason {
+        if !self.owns_event_queue() {
+            return ExitReason::ExternalConnection;
+        }
+        // Make sure the StopHandle can wake the loop up, even if it is requested later
+        let _ = self.stop_handle();
+        // The lock must not be held while dispatching, because the IMConnector could request a StopHandle
+        let wakee_fd = self
+            .stopper
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .wakee
+            .as_raw_fd();
+        let unavailable = Rc::new(Cell::new(false));
+        let sink_unavailable = Rc::downgrade(&unavailable);
+        self.add_event_sink(Box::new(move |event| match sink_unavailable.upgrade() {
+            Some(unavailable) => {
+                if *event == IMEvent::Unavailable {
+                    unavailable.set(true);
+                }
+                true
+            }
+            // The loop returned
+            None => false,
+        }));
+        loop {
+            if matches!(&*self.stopper.lock().unwrap(), Some(stopper) if stopper.take_stopped()) {
+                return ExitReason::Stopped;
+            }
+            if let Err(err) = self.dispatch_pending() {
+                return self.exit_reason(err);
+            }
+            if unavailable.get() {
+                return ExitReason::Unavailable;
+            }
+            // The StopHandle wakes the loop up
+            if let Err(err) = self.wait_and_read(Some(wakee_fd)) {
+                return self.exit_reason(err);
+            }
+        }
+    }
+
+    /// Blocks until events were received and dispatches them. Returns the number of dispatched events
+    ///
+    /// The pending requests are sent before waiting. If a ReconnectPolicy is set and the connection was lost, it waits for the next attempt to reconnect and returns 0 after it.
+    /// Returns an error if the InputService was created on an existing connection, because the application dispatches the events itself
+    pub fn dispatch(&self) -> io::Result<u32> {
+        self.require_own_event_queue()?;
+        loop {
+            let dispatched = self.dispatch_pending()?;
+            if dispatched > 0 {
+                return Ok(dispatched);
+            }
+            let reconnecting = self.reconnect_deadline().is_some();
+            self.wait_and_read(None)?;
+            if reconnecting {
+                // The next attempt is due
+                return self.dispatch_pending();
+            }
+        }
+    }
+
+    /// Sends the pending requests and blocks until events were read or the `wakee_fd` is readable. Returns true if the `wakee_fd` is readable
+    ///
+    /// While the InputService reconnects, the lost connection is not polled. It waits until the next attempt is due instead.
+    /// If reading fails, reconnecting starts if a ReconnectPolicy is set. Otherwise the error is returned.
+    /// The events that were read are dispatched by the next call of `dispatch_pending`
+    pub(crate) fn wait_and_read(&self, wakee_fd: Option<RawFd>) -> io::Result<bool> {
+        // poll ignores negative fds
+        let wakee_fd = wakee_fd.unwrap_or(-1);
+        if let Some(deadline) = self.reconnect_deadline() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            return match poll_readable([wakee_fd], Some(timeout)) {
+                Ok([wakee_ready]) => Ok(wakee_ready),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok(false),
+                Err(err) => Err(err),
+            };
+        }
+        // The requests that do not fit into the socket are sent with the next flush
+        let _ = self.flush();
+        let read_guard = match self.prepare_read() {
+            Some(read_guard) => read_guard,
+            // There are events left that have to be dispatched first
+            None => return Ok(false),
+        };
+        let [connection_ready, wakee_ready] =
+            match poll_readable([self.connection_fd(), wakee_fd], None) {
+                Ok(ready) => ready,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => return Ok(false),
+                Err(err) => return Err(err),
+            };
+        if !connection_ready {
+            read_guard.cancel();
+            return Ok(wakee_ready);
+        }
+        if let Err(err) = read_guard.read_events() {
+            // Reconnecting starts if a ReconnectPolicy is set. Otherwise the connection is lost
+            if err.kind() != io::ErrorKind::WouldBlock {
+                self.connection_lost(err)?;
+            }
+        }
+        Ok(wakee_ready)
+    }
+
+    /// Returns the protocol error if there is one, otherwise the connection was lost
+    fn exit_reason(&self, err: io::Error) -> ExitReason {
+        match self.display.lock().unwrap().protocol_error() {
+            Some(protocol_error) => ExitReason::Protocol(protocol_error),
+            None => ExitReason::ConnectionLost(err),
+        }
+    }
+}
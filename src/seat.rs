@@ -0,0 +1,175 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_seat::{Capability, Event as SeatEvent, WlSeat};
+use wayland_client::{Attached, Main};
+
+/// Information a wl_seat sent about itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeatInfo {
+    /// Id of the wl_seat global
+    pub id: u32,
+    /// Name of the seat. It is None if the seat did not send one, e.g. because its version is too old
+    pub name: Option<String>,
+    /// Capabilities of the seat
+    pub capabilities: Capability,
+}
+
+/// Selects the wl_seat the input method and the virtual keyboard are created for
+#[derive(Default)]
+pub enum SeatSelector {
+    /// The first seat the compositor advertised
+    #[default]
+    First,
+    /// The seat with the given name
+    Name(String),
+    /// The first seat for which the predicate returns true
+    Predicate(Box<dyn Fn(&SeatInfo) -> bool>),
+}
+
+impl SeatSelector {
+    /// Returns true if the seat gets selected
+    pub fn matches(&self, seat: &SeatInfo) -> bool {
+        match self {
+            SeatSelector::First => true,
+            SeatSelector::Name(name) => seat.name.as_ref() == Some(name),
+            SeatSelector::Predicate(predicate) => predicate(seat),
+        }
+    }
+
+    /// Returns the position of the first seat that matches
+    ///
+    /// Seats that did not send all information about themselves yet are skipped, so they are not matched against a partial SeatInfo
+    pub(crate) fn select(&self, seats: &[(SeatInfo, bool)]) -> Option<usize> {
+        seats
+            .iter()
+            .position(|(seat, complete)| *complete && self.matches(seat))
+    }
+}
+
+impl std::fmt::Debug for SeatSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeatSelector::First => f.write_str("First"),
+            SeatSelector::Name(name) => f.debug_tuple("Name").field(name).finish(),
+            SeatSelector::Predicate(_) => f.write_str("Predicate(..)"),
+        }
+    }
+}
+
+/// A bound wl_seat and the information it sent about itself
+#[derive(Debug, Clone)]
+pub(crate) struct TrackedSeat {
+    pub seat: Main<WlSeat>,
+    pub info: Arc<Mutex<SeatInfo>>,
+    /// Set once the seat sent its capabilities and, since version 2, its name
+    complete: Arc<AtomicBool>,
+}
+
+impl TrackedSeat {
+    /// Returns the information about the seat and whether it is complete
+    pub fn info(&self) -> (SeatInfo, bool) {
+        (
+            self.info.lock().unwrap().clone(),
+            self.complete.load(Ordering::SeqCst),
+        )
+    }
+}
+
+/// Binds the wl_seat global and keeps track of its name and capabilities
+///
+/// The callback gets called every time the seat sent new information about itself, once its information is complete
+pub(crate) fn bind_seat<F: Fn() + 'static>(
+    registry: &Attached<WlRegistry>,
+    id: u32,
+    version: u32,
+    callback: F,
+) -> TrackedSeat {
+    let seat = registry.bind::<WlSeat>(version, id);
+    let info = Arc::new(Mutex::new(SeatInfo {
+        id,
+        name: None,
+        capabilities: Capability::empty(),
+    }));
+    let complete = Arc::new(AtomicBool::new(false));
+    let seat_info = info.clone();
+    let seat_complete = complete.clone();
+    // Seats older than version 2 do not send a name
+    let mut received_name = version < 2;
+    let mut received_capabilities = false;
+    seat.quick_assign(move |_, event, _| {
+        {
+            let mut seat_info = seat_info.lock().unwrap();
+            match event {
+                SeatEvent::Name { name } => {
+                    seat_info.name = Some(name);
+                    received_name = true;
+                }
+                SeatEvent::Capabilities { capabilities } => {
+                    seat_info.capabilities = capabilities;
+                    received_capabilities = true;
+                }
+                _ => return,
+            }
+        }
+        if received_name && received_capabilities {
+            seat_complete.store(true, Ordering::SeqCst);
+            callback();
+        }
+    });
+    TrackedSeat {
+        seat,
+        info,
+        complete,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seat_info(id: u32, name: Option<&str>) -> SeatInfo {
+        SeatInfo {
+            id,
+            name: name.map(str::to_string),
+            capabilities: Capability::Keyboard,
+        }
+    }
+
+    #[test]
+    fn skips_partial_seats() {
+        // The capabilities of seat0 arrived, but its name did not
+        let seats = [
+            (seat_info(1, None), false),
+            (seat_info(2, Some("seat1")), true),
+        ];
+        assert_eq!(SeatSelector::First.select(&seats), Some(1));
+        let not_seat0 =
+            SeatSelector::Predicate(Box::new(|seat| seat.name.as_deref() != Some("seat0")));
+        assert!(not_seat0.matches(&seats[0].0));
+        assert_eq!(not_seat0.select(&seats), Some(1));
+    }
+
+    #[test]
+    fn selects_complete_seats() {
+        let seats = [
+            (seat_info(1, Some("seat0")), true),
+            (seat_info(2, Some("seat1")), true),
+        ];
+        assert_eq!(SeatSelector::First.select(&seats), Some(0));
+        assert_eq!(
+            SeatSelector::Name("seat1".to_string()).select(&seats),
+            Some(1)
+        );
+        assert_eq!(SeatSelector::Name("seat2".to_string()).select(&seats), None);
+        let not_seat0 =
+            SeatSelector::Predicate(Box::new(|seat| seat.name.as_deref() != Some("seat0")));
+        assert_eq!(not_seat0.select(&seats), Some(1));
+    }
+
+    #[test]
+    fn name_does_not_match_missing_name() {
+        assert!(!SeatSelector::Name("seat0".to_string()).matches(&seat_info(1, None)));
+        assert!(SeatSelector::First.matches(&seat_info(1, None)));
+    }
+}
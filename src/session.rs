@@ -0,0 +1,124 @@
+use crate::im_state::ImState;
+use crate::{CommitStatus, InputService, SubmitError, SurroundingText};
+use std::sync::{Arc, Mutex, Weak};
+use wayland_protocols::unstable::text_input::v3::client::zwp_text_input_v3::{
+    ContentHint, ContentPurpose,
+};
+
+/// The activation of a text input
+///
+/// A session is handed out when the input method gets activated and becomes stale when it is deactivated or activated again.
+/// Requests made through a stale session return SubmitError::NotActive, so they cannot reach a text input that got focused later
+#[derive(Debug, Clone)]
+pub struct ImSession {
+    im_state: Weak<Mutex<ImState>>,
+    generation: u64,
+    content_hint: ContentHint,
+    content_purpose: ContentPurpose,
+    surrounding_text: SurroundingText,
+}
+
+impl ImSession {
+    /// Creates a session for the current activation
+    pub(crate) fn new(im_state: &Arc<Mutex<ImState>>, state: &ImState) -> Self {
+        Self {
+            im_state: Arc::downgrade(im_state),
+            generation: state.generation,
+            content_hint: state.state.content_hint,
+            content_purpose: state.state.content_purpose,
+            surrounding_text: state.state.surrounding_text.clone(),
+        }
+    }
+
+    /// Returns the content hint the text input had when the session started
+    pub fn content_hint(&self) -> ContentHint {
+        self.content_hint
+    }
+
+    /// Returns the content purpose the text input had when the session started
+    pub fn content_purpose(&self) -> ContentPurpose {
+        self.content_purpose
+    }
+
+    /// Returns the surrounding text the text input had when the session started
+    ///
+    /// The current one is part of `InputService::input_method_state`
+    pub fn surrounding_text(&self) -> &SurroundingText {
+        &self.surrounding_text
+    }
+
+    /// Returns true if the text input of the session is still active
+    pub fn is_active(&self) -> bool {
+        match self.im_state.upgrade() {
+            Some(im_state) => {
+                let im_state = im_state.lock().unwrap();
+                im_state.session_active() && im_state.generation == self.generation
+            }
+            // The input method was destroyed or the InputService reconnected
+            None => false,
+        }
+    }
+
+    /// Sends a 'commit_string' request if the session is active
+    pub fn commit_string(&self, service: &InputService, text: String) -> Result<(), SubmitError> {
+        self.check_active(service)?;
+        service.commit_string(text)
+    }
+
+    /// Sends a 'delete_surrounding_text' request if the session is active
+    pub fn delete_surrounding_text(
+        &self,
+        service: &InputService,
+        before: usize,
+        after: usize,
+    ) -> Result<(), SubmitError> {
+        self.check_active(service)?;
+        service.delete_surrounding_text(before, after)
+    }
+
+    /// Sends a 'set_preedit_string' request if the session is active
+    pub fn set_preedit(
+        &self,
+        service: &InputService,
+        text: String,
+        cursor: Option<(usize, usize)>,
+    ) -> Result<(), SubmitError> {
+        self.check_active(service)?;
+        service.set_preedit(text, cursor)
+    }
+
+    /// Sends a 'commit' request if the session is active
+    pub fn commit(&self, service: &InputService) -> Result<CommitStatus, SubmitError> {
+        self.check_active(service)?;
+        service.commit()
+    }
+
+    /// Returns SubmitError::NotActive if the session is stale or belongs to another InputService
+    fn check_active(&self, service: &InputService) -> Result<(), SubmitError> {
+        let belongs_to_service =
+            match (&service.globals.lock().unwrap().im, self.im_state.upgrade()) {
+                (Some((_, im_state)), Some(session_im_state)) => {
+                    Arc::ptr_eq(im_state, &session_im_state)
+                }
+                _ => false,
+            };
+        match belongs_to_service && self.is_active() {
+            true => Ok(()),
+            false => Err(SubmitError::NotActive),
+        }
+    }
+}
+
+impl InputService {
+    /// Returns the session of the active text input or None if the input method is not active
+    pub fn session(&self) -> Option<ImSession> {
+        let globals = self.globals.lock().unwrap();
+        let (_, im_state) = globals.im.as_ref()?;
+        let state = im_state.lock().unwrap();
+        // After an 'activate' the session only starts once its state was applied with 'done'
+        match state.session_active() {
+            true => Some(ImSession::new(im_state, &state)),
+            false => None,
+        }
+    }
+}
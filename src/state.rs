@@ -0,0 +1,174 @@
+use crate::{IMEvent, SurroundingText};
+use bitflags::bitflags;
+use wayland_protocols::unstable::text_input::v3::client::zwp_text_input_v3::{
+    ChangeCause, ContentHint, ContentPurpose,
+};
+
+/// State of the text input the input method is working on
+///
+/// The compositor sends the state in several events. It is applied together when 'done' is received
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputMethodState {
+    /// True if a text input is focused
+    pub active: bool,
+    /// The text around the cursor
+    pub surrounding_text: SurroundingText,
+    /// The reason the surrounding text changed
+    pub change_cause: ChangeCause,
+    pub content_hint: ContentHint,
+    pub content_purpose: ContentPurpose,
+}
+
+impl Default for InputMethodState {
+    /// The state of an inactive input method, like the protocol defines it
+    fn default() -> Self {
+        Self {
+            active: false,
+            surrounding_text: SurroundingText::default(),
+            change_cause: ChangeCause::InputMethod,
+            content_hint: ContentHint::None,
+            content_purpose: ContentPurpose::Normal,
+        }
+    }
+}
+
+bitflags! {
+    /// The fields of the InputMethodState that changed with a 'done'
+    pub struct StateChanges: u32 {
+        const ACTIVE = 1;
+        /// The surrounding text, the cursor or the anchor
+        const SURROUNDING_TEXT = 2;
+        const CHANGE_CAUSE = 4;
+        /// The content hint or the content purpose
+        const CONTENT_TYPE = 8;
+    }
+}
+
+impl InputMethodState {
+    /// Updates the pending state with the event
+    ///
+    /// 'activate' and 'deactivate' reset the state, because the text input has to send it again
+    pub(crate) fn update(&mut self, event: &IMEvent) {
+        match event {
+            IMEvent::Activate => {
+                *self = Self {
+                    active: true,
+                    ..Self::default()
+                }
+            }
+            IMEvent::Deactivate => *self = Self::default(),
+            IMEvent::SurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => {
+                self.surrounding_text =
+                    SurroundingText::from_protocol(text.clone(), *cursor, *anchor)
+            }
+            IMEvent::TextChangeCause(change_cause) => self.change_cause = *change_cause,
+            IMEvent::ContentType { hint, purpose } => {
+                self.content_hint = *hint;
+                self.content_purpose = *purpose;
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns the fields that differ from the `previous` state
+    pub(crate) fn changes(&self, previous: &Self) -> StateChanges {
+        let mut changes = StateChanges::empty();
+        changes.set(StateChanges::ACTIVE, self.active != previous.active);
+        changes.set(
+            StateChanges::SURROUNDING_TEXT,
+            self.surrounding_text != previous.surrounding_text,
+        );
+        changes.set(
+            StateChanges::CHANGE_CAUSE,
+            self.change_cause != previous.change_cause,
+        );
+        changes.set(
+            StateChanges::CONTENT_TYPE,
+            self.content_hint != previous.content_hint
+                || self.content_purpose != previous.content_purpose,
+        );
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surrounding_text(text: &str, cursor: usize) -> IMEvent {
+        IMEvent::SurroundingText {
+            text: text.to_string(),
+            cursor,
+            anchor: cursor,
+        }
+    }
+
+    #[test]
+    fn activate_and_deactivate_reset() {
+        let mut state = InputMethodState::default();
+        state.update(&IMEvent::Activate);
+        state.update(&surrounding_text("abc", 1));
+        state.update(&IMEvent::TextChangeCause(ChangeCause::Other));
+        state.update(&IMEvent::Activate);
+        assert_eq!(
+            state,
+            InputMethodState {
+                active: true,
+                ..InputMethodState::default()
+            }
+        );
+        state.update(&surrounding_text("abc", 1));
+        state.update(&IMEvent::Deactivate);
+        assert_eq!(state, InputMethodState::default());
+    }
+
+    #[test]
+    fn update_sets_fields() {
+        let mut state = InputMethodState::default();
+        state.update(&surrounding_text("aé", 2));
+        state.update(&IMEvent::TextChangeCause(ChangeCause::Other));
+        state.update(&IMEvent::ContentType {
+            hint: ContentHint::Spellcheck,
+            purpose: ContentPurpose::Email,
+        });
+        // The cursor inside 'é' is moved to its start
+        assert_eq!(state.surrounding_text.cursor(), 1);
+        assert_eq!(state.change_cause, ChangeCause::Other);
+        assert_eq!(state.content_hint, ContentHint::Spellcheck);
+        assert_eq!(state.content_purpose, ContentPurpose::Email);
+        // Other events do not change the state
+        let previous = state.clone();
+        state.update(&IMEvent::Done);
+        assert_eq!(state, previous);
+    }
+
+    #[test]
+    fn changes_flag_changed_fields() {
+        let previous = InputMethodState::default();
+        assert_eq!(previous.changes(&previous), StateChanges::empty());
+        let mut state = previous.clone();
+        state.update(&IMEvent::Activate);
+        assert_eq!(state.changes(&previous), StateChanges::ACTIVE);
+        let previous = state.clone();
+        state.update(&surrounding_text("abc", 3));
+        state.update(&IMEvent::ContentType {
+            hint: ContentHint::None,
+            purpose: ContentPurpose::Number,
+        });
+        assert_eq!(
+            state.changes(&previous),
+            StateChanges::SURROUNDING_TEXT | StateChanges::CONTENT_TYPE
+        );
+        let previous = state.clone();
+        state.update(&surrounding_text("abc", 2));
+        state.update(&IMEvent::TextChangeCause(ChangeCause::Other));
+        assert_eq!(
+            state.changes(&previous),
+            StateChanges::SURROUNDING_TEXT | StateChanges::CHANGE_CAUSE
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use crate::SubmitError;
+use std::convert::TryInto;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The text around the cursor of the text input
+///
+/// The protocol uses byte offsets into the UTF-8 text. They can be converted to char and grapheme offsets
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SurroundingText {
+    text: String,
+    cursor: usize,
+    anchor: usize,
+}
+
+impl SurroundingText {
+    /// Creates a surrounding text with the byte offsets of the cursor and the selection anchor
+    ///
+    /// Returns SubmitError::InvalidOffset if an offset exceeds the text or lies inside a UTF-8 sequence
+    pub fn new(text: String, cursor: usize, anchor: usize) -> Result<Self, SubmitError> {
+        if !text.is_char_boundary(cursor) || !text.is_char_boundary(anchor) {
+            return Err(SubmitError::InvalidOffset);
+        }
+        Ok(Self {
+            text,
+            cursor,
+            anchor,
+        })
+    }
+
+    /// Creates the surrounding text the compositor sent
+    ///
+    /// Invalid offsets are moved to the start of the character they point into
+    pub(crate) fn from_protocol(text: String, cursor: usize, anchor: usize) -> Self {
+        let cursor = floor_char_boundary(&text, cursor);
+        let anchor = floor_char_boundary(&text, anchor);
+        Self {
+            text,
+            cursor,
+            anchor,
+        }
+    }
+
+    /// Returns the text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the byte offset of the cursor
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Returns the byte offset of the selection anchor. It equals the cursor if nothing is selected
+    pub fn anchor(&self) -> usize {
+        self.anchor
+    }
+
+    /// Converts a byte offset to the number of chars before it
+    ///
+    /// Returns SubmitError::InvalidOffset if the offset exceeds the text or lies inside a UTF-8 sequence
+    pub fn byte_to_char(&self, byte_offset: usize) -> Result<usize, SubmitError> {
+        if !self.text.is_char_boundary(byte_offset) {
+            return Err(SubmitError::InvalidOffset);
+        }
+        Ok(self.text[..byte_offset].chars().count())
+    }
+
+    /// Converts a number of chars to the byte offset after them
+    ///
+    /// Returns SubmitError::InvalidOffset if the text has fewer chars
+    pub fn char_to_byte(&self, char_offset: usize) -> Result<usize, SubmitError> {
+        self.text
+            .char_indices()
+            .map(|(byte_offset, _)| byte_offset)
+            .chain(Some(self.text.len()))
+            .nth(char_offset)
+            .ok_or(SubmitError::InvalidOffset)
+    }
+
+    /// Converts a byte offset to the number of graphemes before it
+    ///
+    /// Returns SubmitError::InvalidOffset if the offset exceeds the text or does not lie between two graphemes
+    pub fn byte_to_grapheme(&self, byte_offset: usize) -> Result<usize, SubmitError> {
+        self.grapheme_boundaries()
+            .position(|boundary| boundary == byte_offset)
+            .ok_or(SubmitError::InvalidOffset)
+    }
+
+    /// Converts a number of graphemes to the byte offset after them
+    ///
+    /// Returns SubmitError::InvalidOffset if the text has fewer graphemes
+    pub fn grapheme_to_byte(&self, grapheme_offset: usize) -> Result<usize, SubmitError> {
+        self.grapheme_boundaries()
+            .nth(grapheme_offset)
+            .ok_or(SubmitError::InvalidOffset)
+    }
+
+    /// Returns the length in bytes of up to `count` graphemes before the selection
+    pub fn graphemes_before_cursor(&self, count: usize) -> usize {
+        let start = self.cursor.min(self.anchor);
+        self.text[..start]
+            .graphemes(true)
+            .rev()
+            .take(count)
+            .map(str::len)
+            .sum()
+    }
+
+    /// Returns the length in bytes of up to `count` graphemes after the selection
+    pub fn graphemes_after_cursor(&self, count: usize) -> usize {
+        let end = self.cursor.max(self.anchor);
+        self.text[end..]
+            .graphemes(true)
+            .take(count)
+            .map(str::len)
+            .sum()
+    }
+
+    /// Checks that deleting the bytes around the selection does not split a UTF-8 sequence
+    ///
+    /// The text only contains the area around the cursor, so deleting past its ends is allowed
+    pub(crate) fn check_deletion(&self, before: usize, after: usize) -> Result<(), SubmitError> {
+        if TryInto::<u32>::try_into(before).is_err() || TryInto::<u32>::try_into(after).is_err() {
+            return Err(SubmitError::InvalidOffset);
+        }
+        let start = self.cursor.min(self.anchor);
+        let end = self.cursor.max(self.anchor);
+        let splits_start = matches!(start.checked_sub(before), Some(offset) if !self.text.is_char_boundary(offset));
+        let splits_end = matches!(end.checked_add(after), Some(offset) if offset < self.text.len() && !self.text.is_char_boundary(offset));
+        if splits_start || splits_end {
+            return Err(SubmitError::InvalidOffset);
+        }
+        Ok(())
+    }
+
+    /// Returns the byte offsets between the graphemes, including the start and the end of the text
+    fn grapheme_boundaries(&self) -> impl Iterator<Item = usize> + '_ {
+        self.text
+            .grapheme_indices(true)
+            .map(|(byte_offset, _)| byte_offset)
+            .chain(Some(self.text.len()))
+    }
+}
+
+/// Returns the largest char boundary that is not greater than the offset
+fn floor_char_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Byte offsets: a 0, é 1, € 3, e + combining acute 6, 😀 9, b 13, end 14
+    const TEXT: &str = "aé€e\u{301}😀b";
+
+    fn text(cursor: usize, anchor: usize) -> SurroundingText {
+        SurroundingText::new(TEXT.to_string(), cursor, anchor).unwrap()
+    }
+
+    #[test]
+    fn new_rejects_invalid_offsets() {
+        assert!(SurroundingText::new(TEXT.to_string(), 14, 0).is_ok());
+        assert!(matches!(
+            SurroundingText::new(TEXT.to_string(), 2, 2),
+            Err(SubmitError::InvalidOffset)
+        ));
+        assert!(matches!(
+            SurroundingText::new(TEXT.to_string(), 1, 15),
+            Err(SubmitError::InvalidOffset)
+        ));
+    }
+
+    #[test]
+    fn from_protocol_clamps_offsets() {
+        let surrounding_text = SurroundingText::from_protocol(TEXT.to_string(), 4, 100);
+        assert_eq!(surrounding_text.cursor(), 3);
+        assert_eq!(surrounding_text.anchor(), 14);
+    }
+
+    #[test]
+    fn floor_char_boundary_offsets() {
+        assert_eq!(floor_char_boundary(TEXT, 0), 0);
+        assert_eq!(floor_char_boundary(TEXT, 2), 1);
+        assert_eq!(floor_char_boundary(TEXT, 12), 9);
+        assert_eq!(floor_char_boundary(TEXT, 13), 13);
+        assert_eq!(floor_char_boundary(TEXT, 20), 14);
+        assert_eq!(floor_char_boundary("", 1), 0);
+    }
+
+    #[test]
+    fn byte_char_conversion() {
+        let surrounding_text = text(0, 0);
+        assert_eq!(surrounding_text.byte_to_char(0).unwrap(), 0);
+        assert_eq!(surrounding_text.byte_to_char(3).unwrap(), 2);
+        assert_eq!(surrounding_text.byte_to_char(7).unwrap(), 4);
+        assert_eq!(surrounding_text.byte_to_char(14).unwrap(), 7);
+        assert!(surrounding_text.byte_to_char(10).is_err());
+        assert!(surrounding_text.byte_to_char(15).is_err());
+        assert_eq!(surrounding_text.char_to_byte(2).unwrap(), 3);
+        assert_eq!(surrounding_text.char_to_byte(4).unwrap(), 7);
+        assert_eq!(surrounding_text.char_to_byte(7).unwrap(), 14);
+        assert!(surrounding_text.char_to_byte(8).is_err());
+    }
+
+    #[test]
+    fn byte_grapheme_conversion() {
+        let surrounding_text = text(0, 0);
+        assert_eq!(surrounding_text.byte_to_grapheme(6).unwrap(), 3);
+        assert_eq!(surrounding_text.byte_to_grapheme(9).unwrap(), 4);
+        assert_eq!(surrounding_text.byte_to_grapheme(14).unwrap(), 6);
+        // Between the base char and its combining mark
+        assert!(surrounding_text.byte_to_grapheme(7).is_err());
+        assert!(surrounding_text.byte_to_grapheme(15).is_err());
+        assert_eq!(surrounding_text.grapheme_to_byte(3).unwrap(), 6);
+        assert_eq!(surrounding_text.grapheme_to_byte(4).unwrap(), 9);
+        assert_eq!(surrounding_text.grapheme_to_byte(6).unwrap(), 14);
+        assert!(surrounding_text.grapheme_to_byte(7).is_err());
+    }
+
+    #[test]
+    fn graphemes_around_cursor() {
+        let surrounding_text = text(9, 9);
+        assert_eq!(surrounding_text.graphemes_before_cursor(1), 3);
+        assert_eq!(surrounding_text.graphemes_before_cursor(2), 6);
+        assert_eq!(surrounding_text.graphemes_before_cursor(100), 9);
+        assert_eq!(surrounding_text.graphemes_after_cursor(1), 4);
+        assert_eq!(surrounding_text.graphemes_after_cursor(100), 5);
+        assert_eq!(surrounding_text.graphemes_after_cursor(0), 0);
+    }
+
+    #[test]
+    fn graphemes_around_selection() {
+        // The selection covers "€e\u{301}" with the cursor at its start
+        let surrounding_text = text(3, 9);
+        assert_eq!(surrounding_text.graphemes_before_cursor(1), 2);
+        assert_eq!(surrounding_text.graphemes_after_cursor(1), 4);
+        let surrounding_text = text(9, 3);
+        assert_eq!(surrounding_text.graphemes_before_cursor(1), 2);
+        assert_eq!(surrounding_text.graphemes_after_cursor(1), 4);
+    }
+
+    #[test]
+    fn check_deletion_boundaries() {
+        let surrounding_text = text(6, 6);
+        assert!(surrounding_text.check_deletion(3, 3).is_ok());
+        assert!(surrounding_text.check_deletion(6, 8).is_ok());
+        // Inside '€' and inside the combining acute
+        assert!(surrounding_text.check_deletion(2, 0).is_err());
+        assert!(surrounding_text.check_deletion(0, 2).is_err());
+        // Past the ends of the text
+        assert!(surrounding_text.check_deletion(100, 100).is_ok());
+        assert!(surrounding_text
+            .check_deletion(u32::MAX as usize, 0)
+            .is_ok());
+    }
+
+    #[test]
+    fn check_deletion_selection() {
+        // The deletion is relative to the selection, not to the cursor
+        let surrounding_text = text(9, 3);
+        assert!(surrounding_text.check_deletion(2, 4).is_ok());
+        assert!(surrounding_text.check_deletion(1, 0).is_err());
+        assert!(surrounding_text.check_deletion(0, 1).is_err());
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn check_deletion_out_of_range() {
+        let surrounding_text = text(0, 0);
+        let too_long = u32::MAX as usize + 1;
+        assert!(surrounding_text.check_deletion(too_long, 0).is_err());
+        assert!(surrounding_text.check_deletion(0, too_long).is_err());
+    }
+}
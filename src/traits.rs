@@ -1,3 +1,6 @@
+use crate::{ImSession, InputMethodState, KeyState, Rectangle, StateChanges};
+use std::os::unix::io::RawFd;
+use wayland_client::protocol::wl_keyboard::KeymapFormat;
 use wayland_protocols::unstable::text_input::v3::client::zwp_text_input_v3::{
     ChangeCause, ContentHint, ContentPurpose,
 };
@@ -8,11 +11,51 @@ use wayland_protocols::unstable::text_input::v3::client::zwp_text_input_v3::{
 pub trait IMConnector {
     fn activated(&self);
     fn deactivated(&self);
+    /// The cursor and the anchor are byte offsets into the text. `SurroundingText` converts them to char and grapheme offsets
+    ///
+    /// The value is pending and only applies once `done` is called. `state_changed` gets the applied state instead
     fn surrounding_text(&self, text: String, cursor: usize, anchor: usize);
+    /// The value is pending and only applies once `done` is called. `state_changed` gets the applied state instead
     fn text_change_cause(&self, change_cause: ChangeCause);
+    /// The values are pending and only apply once `done` is called. `state_changed` gets the applied state instead
     fn content_type(&self, content_hint: ContentHint, content_purpose: ContentPurpose);
     fn done(&self);
     fn unavailable(&self);
+    /// Gets called after the InputService reconnected to the wayland server
+    ///
+    /// The state of the input method was lost, so it is inactive until the next 'activated'
+    fn reconnected(&self) {}
+    /// Gets called when the area of the text input moved. It is only sent if a popup surface was created
+    fn text_input_rectangle(&self, _rectangle: Rectangle) {}
+    /// Gets called with the state that was applied on 'done', before `done` is called
+    ///
+    /// The changes are the fields that differ from the previously applied state
+    fn state_changed(&self, _state: &InputMethodState, _changes: StateChanges) {}
+    /// Gets called when the state of a new activation was applied, after `state_changed`
+    ///
+    /// Requests made through the session fail with SubmitError::NotActive once the text input is deactivated
+    fn session_started(&self, _session: ImSession) {}
+    /// Gets called when the text input was deactivated before all chunks of a long text were committed
+    ///
+    /// The text is the part that was dropped, so it can be committed again once the text input is activated
+    fn commit_string_dropped(&self, _text: String) {}
+}
+
+/// Trait to receive the events of the hardware keyboard while it is grabbed by the input method
+pub trait KeyboardGrabHandler {
+    /// The keymap the key codes belong to. The handler owns the fd and has to close it
+    fn keymap(&self, format: KeymapFormat, fd: RawFd, size: u32);
+    fn key(&self, serial: u32, time: u32, key: u32, state: KeyState);
+    fn modifiers(
+        &self,
+        serial: u32,
+        mods_depressed: u32,
+        mods_latched: u32,
+        mods_locked: u32,
+        group: u32,
+    );
+    /// The rate is the number of repeated keys per second and the delay is in milliseconds
+    fn repeat_info(&self, rate: i32, delay: i32);
 }
 
 #[derive(Debug, Clone, Copy, Default)]
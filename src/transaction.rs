@@ -0,0 +1,89 @@
+use crate::im_state::MAX_COMMIT_STRING_LEN;
+use crate::{CommitStatus, InputService, Preedit, SubmitError};
+
+/// Collects changes to the text input and sends them with a single 'commit'
+///
+/// The changes are validated together before any request is sent, so either all of them are committed or none
+#[derive(Debug)]
+#[must_use = "The changes are only sent by calling commit"]
+pub struct Transaction<'a> {
+    service: &'a InputService,
+    preedit: Option<(String, Option<(usize, usize)>)>,
+    delete: Option<(usize, usize)>,
+    commit_string: Option<String>,
+}
+
+impl InputService {
+    /// Returns a Transaction to send several changes to the text input with one 'commit'
+    pub fn transaction(&self) -> Transaction<'_> {
+        Transaction {
+            service: self,
+            preedit: None,
+            delete: None,
+            commit_string: None,
+        }
+    }
+}
+
+impl<'a> Transaction<'a> {
+    /// Sets the preedit string. The cursor is the range between two byte offsets into the text. None hides the cursor
+    pub fn preedit<S: Into<String>>(mut self, text: S, cursor: Option<(usize, usize)>) -> Self {
+        self.preedit = Some((text.into(), cursor));
+        self
+    }
+
+    /// Deletes the number of bytes before and after the cursor from the surrounding text
+    pub fn delete_surrounding_text(mut self, before: usize, after: usize) -> Self {
+        self.delete = Some((before, after));
+        self
+    }
+
+    /// Inserts the text at the cursor
+    pub fn commit_string<S: Into<String>>(mut self, text: S) -> Self {
+        self.commit_string = Some(text.into());
+        self
+    }
+
+    /// Validates the changes and sends them in the order the compositor applies them, followed by a 'commit'
+    ///
+    /// Returns SubmitError::InvalidOffset if the preedit cursor or the deletion is invalid.
+    /// Returns SubmitError::TooLong if the commit string does not fit into a single request, because it cannot be split without several commits.
+    /// Returns SubmitError::ChunksPending while parts of a long text are still waiting to be committed, because the changes would be split between commits.
+    /// Nothing is sent in those cases
+    pub fn commit(self) -> Result<CommitStatus, SubmitError> {
+        if matches!(&self.commit_string, Some(text) if text.len() > MAX_COMMIT_STRING_LEN) {
+            return Err(SubmitError::TooLong);
+        }
+        let preedit = match self.preedit {
+            Some((text, cursor)) => Some(Preedit::new(text, cursor)?),
+            None => None,
+        };
+        #[cfg(feature = "debug")]
+        info!(
+            "Commit a transaction: delete {:?}, commit string {:?}, preedit {:?}",
+            self.delete, self.commit_string, preedit
+        );
+        // The globals stay locked, so no other request can be sent in between
+        if let Some((im, im_state)) = &self.service.globals.lock().unwrap().im {
+            // Check if proxy is still alive. If the proxy was dead, the requests would fail silently
+            if !im.as_ref().is_alive() {
+                return Err(SubmitError::NotAlive);
+            }
+            let mut im_state = im_state.lock().unwrap();
+            im_state.check_no_chunks_pending()?;
+            if let Some((before, after)) = self.delete {
+                // The deletion is validated when it is sent, so it has to be the first request
+                im_state.send_delete_surrounding_text(im, before, after)?;
+            }
+            if let Some(text) = self.commit_string {
+                im_state.send_commit_string(im, text)?;
+            }
+            if let Some(preedit) = preedit {
+                im_state.send_preedit(im, preedit)?;
+            }
+            Ok(im_state.send_commit(im))
+        } else {
+            Err(SubmitError::IMNotAvailable)
+        }
+    }
+}
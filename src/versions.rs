@@ -0,0 +1,27 @@
+/// Range of the wl_seat versions that can be bound
+pub(crate) const SEAT_VERSIONS: (u32, u32) = (1, 7);
+/// Range of the zwp_input_method_manager_v2 versions that can be bound
+pub(crate) const IM_MANAGER_VERSIONS: (u32, u32) = (1, 1);
+/// Range of the zwp_virtual_keyboard_manager_v1 versions that can be bound
+pub(crate) const VK_MANAGER_VERSIONS: (u32, u32) = (1, 1);
+
+/// Version of wl_seat that introduced the 'name' event
+const SEAT_NAME_SINCE: u32 = 2;
+
+/// Versions of the protocols that were negotiated with the compositor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolVersions {
+    /// Version of the selected wl_seat or None if no seat is selected
+    pub seat: Option<u32>,
+    /// Version of the bound zwp_input_method_manager_v2 or None if it is unavailable
+    pub input_method_manager: Option<u32>,
+    /// Version of the bound zwp_virtual_keyboard_manager_v1 or None if it is unavailable
+    pub virtual_keyboard_manager: Option<u32>,
+}
+
+impl ProtocolVersions {
+    /// Returns true if the wl_seat sends its name
+    pub fn seat_has_name(&self) -> bool {
+        matches!(self.seat, Some(version) if version >= SEAT_NAME_SINCE)
+    }
+}
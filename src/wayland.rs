@@ -1,53 +1,100 @@
-use wayland_client::{
-    protocol::wl_seat::WlSeat, Display, EventQueue, GlobalError, GlobalManager, Main,
-};
+use crate::globals::{global_callback, GlobalListener, Globals};
+use crate::versions::{IM_MANAGER_VERSIONS, VK_MANAGER_VERSIONS};
+use crate::ConnectError;
+use std::sync::{Arc, Mutex};
+use wayland_client::{Display, EventQueue, GlobalError, GlobalManager, Interface, Main};
 use wayland_protocols::misc::zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2;
 use zwp_virtual_keyboard::virtual_keyboard_unstable_v1::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
 
-fn get_wl_global_mgr(display: Display) -> (EventQueue, GlobalManager) {
+fn get_wl_global_mgr(
+    display: Display,
+    globals: &Arc<Mutex<Globals>>,
+    listener: &GlobalListener,
+) -> Result<(EventQueue, GlobalManager), ConnectError> {
     // Create the event queue
     let mut event_queue = display.create_event_queue();
     // Attach the display
     let attached_display = display.attach(event_queue.token());
 
-    let global_mgr = GlobalManager::new(&attached_display);
+    // The globals get bound when they are advertised and the objects created from them get destroyed when they are removed
+    let global_mgr =
+        GlobalManager::new_with_cb(&attached_display, global_callback(globals, listener));
 
     // sync_roundtrip is a special kind of dispatching for the event queue.
     // Rather than just blocking once waiting for replies, it'll block
     // in a loop until the server has signalled that it has processed and
     // replied accordingly to all requests previously sent by the client.
     //
-    // In our case, this allows us to be sure that after this call returns,
-    // we have received the full list of globals.
-    event_queue
-        .sync_roundtrip(
-            // we don't use a global state for this example
-            &mut (),
-            // The only object that can receive events is the WlRegistry, and the
-            // GlobalManager already takes care of assigning it to a callback, so
-            // we cannot receive orphan events at this point
-            |_, _, _| println!("Event received that was not handled"), // For testing
-                                                                       //|_, _, _| unreachable!(), // Original
-        )
-        .unwrap();
-    (event_queue, global_mgr)
+    // In our case, this allows us to be sure that after the first call returns,
+    // we have received the full list of globals and after the second one,
+    // we have received the names and capabilities of the seats.
+    for _ in 0..2 {
+        event_queue
+            .sync_roundtrip(
+                // we don't use a global state for this example
+                &mut (),
+                // The only objects that can receive events are the WlRegistry and the WlSeats.
+                // They are all assigned to a callback, so we cannot receive orphan events at this point
+                |_, _, _| println!("Event received that was not handled"), // For testing
+                                                                           //|_, _, _| unreachable!(), // Original
+            )
+            .map_err(|err| {
+                // A failed roundtrip is most likely caused by a protocol error
+                match event_queue.display().protocol_error() {
+                    Some(protocol_error) => ConnectError::Protocol(protocol_error),
+                    None => ConnectError::RoundtripFailed(err),
+                }
+            })?;
+    }
+    Ok((event_queue, global_mgr))
 }
 
+/// Binds the globals of the wayland server the `display` is connected to
+///
+/// Returns an error if no seat was selected
+pub fn init_wayland(
+    display: Display,
+    globals: &Arc<Mutex<Globals>>,
+    listener: &GlobalListener,
+) -> Result<(EventQueue, GlobalManager), ConnectError> {
+    let (event_queue, global_mgr) = get_wl_global_mgr(display, globals, listener)?;
+    let globals = globals.lock().unwrap();
+    if globals.seat.is_none() {
+        // Every version of wl_seat gets bound, so the seats are only empty if none is advertised
+        return Err(match globals.seats.is_empty() {
+            true => ConnectError::NoSeat,
+            false => ConnectError::NoMatchingSeat,
+        });
+    }
+    Ok((event_queue, global_mgr))
+}
+
+/// Returns the id of the first global with the interface
+///
+/// This is the global the GlobalManager binds
+pub fn global_id<I: Interface>(global_mgr: &GlobalManager) -> Option<u32> {
+    global_mgr
+        .list()
+        .into_iter()
+        .find(|(_, interface, _)| interface == I::NAME)
+        .map(|(id, _, _)| id)
+}
+
+/// Binds the managers of the input method and the virtual keyboard with the highest version both sides support
+///
+/// A manager whose advertised version is too old is treated like a missing one.
+/// The managers are handled by the event queue the GlobalManager is attached to
 #[allow(clippy::type_complexity)]
-pub fn init_wayland() -> (
-    EventQueue,
-    WlSeat,
+pub fn get_managers(
+    global_mgr: &GlobalManager,
+) -> (
     Result<Main<ZwpInputMethodManagerV2>, GlobalError>,
     Result<Main<ZwpVirtualKeyboardManagerV1>, GlobalError>,
 ) {
-    let display = Display::connect_to_env()
-        .or_else(|_| Display::connect_to_name("wayland-0"))
-        .unwrap();
-    let (event_queue, global_mgr) = get_wl_global_mgr(display);
-    //#[cfg(feature = "debug")]
-    let seat = global_mgr.instantiate_exact::<WlSeat>(7).unwrap();
-    let seat: WlSeat = WlSeat::from(seat.as_ref().clone());
-    let im_mgr = global_mgr.instantiate_exact::<ZwpInputMethodManagerV2>(1);
-    let vk_mgr = global_mgr.instantiate_exact::<ZwpVirtualKeyboardManagerV1>(1);
-    (event_queue, seat, im_mgr, vk_mgr)
+    let (min_version, max_version) = IM_MANAGER_VERSIONS;
+    let im_mgr = global_mgr.instantiate_range::<ZwpInputMethodManagerV2>(min_version, max_version);
+    let (min_version, max_version) = VK_MANAGER_VERSIONS;
+    let vk_mgr =
+        global_mgr.instantiate_range::<ZwpVirtualKeyboardManagerV1>(min_version, max_version);
+    (im_mgr, vk_mgr)
 }